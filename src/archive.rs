@@ -0,0 +1,95 @@
+use crate::config::Config;
+use crate::fetch;
+use chrono::{NaiveDate, Utc};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Where a day's file goes in a local archive, in the same layout as the upstreams.
+fn day_path(dir: &Path, date: NaiveDate) -> PathBuf {
+    dir.join(date.format("%Y/%m/%d/text/noaaport").to_string())
+        .join(format!("TOR_{}.txt", date.format("%Y%m%d")))
+}
+
+/// Writes a file through a temporary one, so an interrupted run never leaves half a day behind.
+fn write_atomic(path: &Path, text: &str) -> std::io::Result<()> {
+    std::fs::create_dir_all(path.parent().unwrap())?;
+    let partial = path.with_extension("txt.partial");
+    std::fs::write(&partial, text)?;
+    std::fs::rename(partial, path)
+}
+
+/// Downloads every day from `from` to `to` into a local archive directory.
+///
+/// Days already in the directory are skipped, so an interrupted run picks up where it left off.
+/// Days no upstream has a file for aren't stored, so a later run tries them again in case the
+/// upstreams were missing them. Today is left out, since it is still changing. Returns how many
+/// days failed.
+pub fn backfill(config: &Config, dir: &Path, from: NaiveDate, to: NaiveDate) -> usize {
+    use futures::{stream, StreamExt};
+
+    // Always download, even if the config reads from this very directory.
+    let config = &Config {
+        local_archive: None,
+        ..config.clone()
+    };
+
+    let yesterday = Utc::now().date_naive().pred_opt().unwrap();
+    if to > yesterday {
+        eprintln!("Stopping at {yesterday}, since later days are still changing");
+    }
+
+    let days: Vec<_> = from
+        .iter_days()
+        .take_while(|&date| date <= to.min(yesterday))
+        .collect();
+    let total = days.len();
+    let missing: Vec<_> = days
+        .into_iter()
+        // Empty files were left by earlier versions for missing days, which are tried again.
+        .filter(|&date| std::fs::metadata(day_path(dir, date)).map_or(true, |meta| meta.len() == 0))
+        .collect();
+    let count = missing.len();
+    println!(
+        "{} of {total} days are already in {}, downloading the other {count}",
+        total - count,
+        dir.display(),
+    );
+
+    let client = &fetch::client(config);
+    let mut downloads = stream::iter(missing)
+        .map(|date| async move {
+            let result = fetch::fetch_day(client, config, date, "index").await;
+            (date, result)
+        })
+        .buffer_unordered(config.concurrency);
+    let (mut done, mut failed, mut empty) = (0, 0, 0);
+
+    tokio::runtime::Runtime::new().unwrap().block_on(async {
+        while let Some((date, result)) = downloads.next().await {
+            let result = result.map_err(|e| e.to_string()).and_then(|text| match text.is_empty() {
+                true => {
+                    empty += 1;
+                    Ok(())
+                }
+                false => write_atomic(&day_path(dir, date), &text).map_err(|e| e.to_string()),
+            });
+            if let Err(e) = result {
+                eprintln!("\rFailed to download {date}: {e}");
+                failed += 1;
+            }
+
+            done += 1;
+            let percent = done as f32 / count.max(1) as f32 * 100.;
+            eprint!("\r[{done}/{count}] {percent:.1}%, last {date}");
+            let _ = std::io::stderr().flush();
+        }
+    });
+
+    if count > 0 {
+        eprintln!();
+    }
+    if empty > 0 {
+        println!("{empty} days had no file upstream, and will be looked for again next run");
+    }
+    failed
+}
@@ -0,0 +1,90 @@
+use crate::config::Config;
+use crate::query::Param;
+use chrono::{DateTime, Utc};
+use lazy_static::lazy_static;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// A rendered response body.
+struct Entry {
+    body: Arc<Vec<u8>>,
+    content_type: &'static str,
+    expires: Instant,
+}
+
+lazy_static! {
+    static ref CACHE: Mutex<HashMap<String, Entry>> = Mutex::new(HashMap::new());
+}
+
+/// Builds the cache key of a request from its path and parameters.
+///
+/// Parameters are sorted and the ones set to their default value are dropped, so equivalent urls
+/// share one entry.
+pub fn key(path: &str, params: &BTreeMap<String, String>, accepted: &[Param]) -> String {
+    let mut key = path.to_string();
+    for (name, value) in params {
+        let is_default = accepted
+            .iter()
+            .any(|param| param.name == name && param.default == Some(value.as_str()));
+        if !is_default {
+            key += &format!("&{name}={value}");
+        }
+    }
+
+    key
+}
+
+/// How long a response covering a range ending at `end` is kept.
+///
+/// Ranges including today change as warnings are issued, so they are only kept briefly.
+pub fn ttl(config: &Config, end: DateTime<Utc>) -> Duration {
+    match end.date_naive() >= Utc::now().date_naive() {
+        true => Duration::from_secs(config.cache.today_ttl),
+        false => Duration::from_secs(config.cache.past_ttl),
+    }
+}
+
+/// Returns a cached response body and its content type, if there is one that hasn't expired.
+pub fn get(key: &str) -> Option<(Arc<Vec<u8>>, &'static str)> {
+    let cache = CACHE.lock().unwrap();
+    cache
+        .get(key)
+        .filter(|entry| entry.expires > Instant::now())
+        .map(|entry| (entry.body.clone(), entry.content_type))
+}
+
+/// Caches a response body for `ttl`, making room by dropping the entries closest to expiring.
+pub fn put(config: &Config, key: String, body: Vec<u8>, content_type: &'static str, ttl: Duration) {
+    let max_entries = config.cache.max_entries;
+    if max_entries == 0 || ttl.is_zero() {
+        return;
+    }
+
+    let mut cache = CACHE.lock().unwrap();
+    let now = Instant::now();
+    cache.retain(|_, entry| entry.expires > now);
+
+    while cache.len() >= max_entries {
+        let soonest = cache
+            .iter()
+            .min_by_key(|(_, entry)| entry.expires)
+            .map(|(key, _)| key.clone())
+            .unwrap();
+        cache.remove(&soonest);
+    }
+
+    cache.insert(
+        key,
+        Entry {
+            body: Arc::new(body),
+            content_type,
+            expires: now + ttl,
+        },
+    );
+}
+
+/// Drops every cached response, such as when the config changes how they are rendered.
+pub fn clear() {
+    CACHE.lock().unwrap().clear();
+}
@@ -0,0 +1,508 @@
+use crate::query::Palette;
+use crate::schedule::Schedule;
+use crate::warning::Severity;
+use lazy_static::lazy_static;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+/// The line style used for one severity class.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Style {
+    pub color: String,
+    pub width: f32,
+    /// Draws the outline dashed, with dashes this long in degrees.
+    pub dash: Option<f32>,
+    /// The opacity of the fill with `mode=fill`, from 0 to 255.
+    #[serde(default = "Style::default_fill_alpha")]
+    pub fill_alpha: u8,
+}
+
+impl Style {
+    fn new(color: &str, width: f32) -> Self {
+        Self {
+            color: color.to_string(),
+            width,
+            dash: None,
+            fill_alpha: Self::default_fill_alpha(),
+        }
+    }
+
+    fn default_fill_alpha() -> u8 {
+        64
+    }
+}
+
+/// The styles for each warning severity.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Colors {
+    pub emergency: Style,
+    pub pds: Style,
+    pub observed: Style,
+    pub default: Style,
+    /// Used instead of `observed` and `default` for waterspouts.
+    pub waterspout: Style,
+    /// Used for warnings that were not verified by a report, if set.
+    pub unverified: Option<Style>,
+    /// Used for warnings that recently expired, in the live placefile.
+    pub expired: Style,
+}
+
+impl Default for Colors {
+    fn default() -> Self {
+        Self {
+            emergency: Style::new("0 0 0", 5.),
+            pds: Style::new("255 0 255", 4.),
+            observed: Style::new("150 0 0", 3.5),
+            default: Style::new("255 0 0", 3.),
+            waterspout: Style::new("0 170 255", 3.),
+            unverified: None,
+            expired: Style {
+                dash: Some(0.05),
+                ..Style::new("150 150 150", 1.5)
+            },
+        }
+    }
+}
+
+impl Colors {
+    /// Returns these styles with the colors of a preset palette, keeping the widths and dashes.
+    pub fn with_palette(&self, palette: Palette) -> Self {
+        let [emergency, pds, observed, default, waterspout, unverified] = match palette {
+            Palette::Cvd => [
+                "0 114 178",
+                "204 121 167",
+                "213 94 0",
+                "240 228 66",
+                "86 180 233",
+                "153 153 153",
+            ],
+            Palette::Dark => [
+                "255 255 255",
+                "255 64 255",
+                "255 48 48",
+                "255 150 0",
+                "0 210 255",
+                "170 170 170",
+            ],
+            Palette::Light => [
+                "0 0 0",
+                "170 0 170",
+                "140 0 0",
+                "220 0 0",
+                "0 110 200",
+                "110 110 110",
+            ],
+        };
+
+        let recolor = |style: &Style, color: &str| Style {
+            color: color.to_string(),
+            ..style.clone()
+        };
+
+        Self {
+            emergency: recolor(&self.emergency, emergency),
+            pds: recolor(&self.pds, pds),
+            observed: recolor(&self.observed, observed),
+            default: recolor(&self.default, default),
+            waterspout: recolor(&self.waterspout, waterspout),
+            unverified: self.unverified.as_ref().map(|style| recolor(style, unverified)),
+            expired: self.expired.clone(),
+        }
+    }
+
+    /// Returns these styles with every fill drawn at the same opacity.
+    pub fn with_fill_alpha(&self, fill_alpha: u8) -> Self {
+        let refill = |style: &Style| Style {
+            fill_alpha,
+            ..style.clone()
+        };
+
+        Self {
+            emergency: refill(&self.emergency),
+            pds: refill(&self.pds),
+            observed: refill(&self.observed),
+            default: refill(&self.default),
+            waterspout: refill(&self.waterspout),
+            unverified: self.unverified.as_ref().map(refill),
+            expired: self.expired.clone(),
+        }
+    }
+}
+
+/// An icon sheet for marking warnings, in the placefile `IconFile` format.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Icons {
+    /// The url of the icon sheet image.
+    pub file: String,
+    pub width: u32,
+    pub height: u32,
+    /// The icon number drawn at the center of waterspout warnings.
+    pub waterspout: Option<u32>,
+    /// The icon number drawn at the storm's location, rotated to where it is heading. The icon
+    /// should point north.
+    pub motion: Option<u32>,
+}
+
+/// Settings for following today's warnings as they are issued.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Live {
+    pub enabled: bool,
+    /// How often to poll the upstreams for new warnings, in seconds.
+    pub interval: u64,
+    /// How long the live placefile keeps showing a warning after it expires, in minutes.
+    pub expired_grace: i64,
+}
+
+impl Default for Live {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            interval: 60,
+            expired_grace: 30,
+        }
+    }
+}
+
+/// Settings for keeping rendered responses, so repeated requests skip the work.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Cache {
+    /// The most responses kept at once. 0 disables the cache.
+    pub max_entries: usize,
+    /// How long to keep responses for ranges including today, in seconds.
+    pub today_ttl: u64,
+    /// How long to keep responses for ranges entirely in the past, in seconds.
+    pub past_ttl: u64,
+}
+
+impl Default for Cache {
+    fn default() -> Self {
+        Self {
+            max_entries: 64,
+            today_ttl: 60,
+            past_ttl: 86400,
+        }
+    }
+}
+
+/// Where the warning files are downloaded from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Source {
+    /// The noaaport text archives in `upstreams`.
+    #[default]
+    Upstreams,
+    /// A public S3 bucket, such as one from the NOAA Open Data Dissemination program.
+    S3,
+}
+
+/// A public S3 bucket holding the warning files.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct S3Archive {
+    pub bucket: String,
+    pub region: String,
+    /// The key of each day's file, as a strftime format.
+    pub key: String,
+    /// Overrides the AWS endpoint, for S3-compatible mirrors.
+    pub endpoint: Option<String>,
+}
+
+impl Default for S3Archive {
+    fn default() -> Self {
+        Self {
+            bucket: String::new(),
+            region: "us-east-1".to_string(),
+            key: "%Y/%m/%d/text/noaaport/TOR_%Y%m%d.txt".to_string(),
+            endpoint: None,
+        }
+    }
+}
+
+/// Credentials for signing uploads to S3.
+#[derive(Debug, Clone, Deserialize)]
+pub struct S3Credentials {
+    pub region: String,
+    pub access_key: String,
+    pub secret_key: String,
+}
+
+/// A remote location that generated placefiles are uploaded to.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Publish {
+    /// The url the placefile is uploaded to with an HTTP PUT, or a `file://` path it is written to.
+    pub url: String,
+    /// How many days the placefile covers.
+    pub days: u32,
+    /// How many days before today the covered range ends.
+    pub ago: u32,
+    /// The query string the placefile is generated with, like a request to `/warnings.txt`. Its
+    /// own `start`, `end` or `range` replace `days` and `ago`.
+    pub query: String,
+    /// How often to upload, in seconds. 0 only uploads after live updates.
+    pub interval: u64,
+    /// A cron schedule to upload on, in UTC, instead of every `interval` seconds.
+    pub schedule: Option<Schedule>,
+    /// Whether to also upload whenever live mode announces an update.
+    pub after_live: bool,
+    /// Extra headers sent with the upload.
+    pub headers: BTreeMap<String, String>,
+    /// Signs the upload for S3 if set.
+    pub s3: Option<S3Credentials>,
+}
+
+impl Default for Publish {
+    fn default() -> Self {
+        Self {
+            url: String::new(),
+            days: 1,
+            ago: 0,
+            query: String::new(),
+            interval: 300,
+            schedule: None,
+            after_live: false,
+            headers: BTreeMap::new(),
+            s3: None,
+        }
+    }
+}
+
+/// The message format a webhook expects.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WebhookKind {
+    Discord,
+    Slack,
+    Json,
+}
+
+/// A url notified when live mode sees a dangerous warning.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Webhook {
+    pub url: String,
+    #[serde(default = "Webhook::default_kind")]
+    pub kind: WebhookKind,
+    /// The severities that trigger the webhook.
+    #[serde(default = "Webhook::default_severities")]
+    pub severities: Vec<Severity>,
+}
+
+impl Webhook {
+    fn default_kind() -> WebhookKind {
+        WebhookKind::Json
+    }
+
+    fn default_severities() -> Vec<Severity> {
+        vec![Severity::Emergency, Severity::Pds]
+    }
+}
+
+/// The server configuration, read from a TOML file.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Where to download warning files from.
+    pub source: Source,
+    pub s3: S3Archive,
+    /// The base urls of the noaaport text archive, tried in order.
+    pub upstreams: Vec<String>,
+    /// How long to wait for an upstream before trying the next one, in seconds.
+    pub upstream_timeout: u64,
+    /// How to reach whoever runs this server, such as an email address, sent in the User-Agent.
+    pub contact: Option<String>,
+    /// The proxy for all outgoing requests. Defaults to the `HTTPS_PROXY` and `HTTP_PROXY` variables.
+    pub proxy: Option<String>,
+    /// A directory of `TOR_YYYYMMDD.txt` files to read instead of the upstreams, for working offline.
+    pub local_archive: Option<PathBuf>,
+    /// An NCEI Storm Events details CSV, or a directory of them, used to verify warnings.
+    pub storm_events: Option<PathBuf>,
+    /// The base url of the API serving single products by id, for `/product/{id}`.
+    pub product_api: String,
+    /// The url of the GeoJSON Local Storm Report service used by `verified=1`.
+    pub lsr_api: String,
+    /// The most requests per second sent to the upstreams by the whole server. 0 disables the limit.
+    pub rate_limit: f64,
+    /// How many upstream requests can be sent at once after a quiet period.
+    pub burst: u32,
+    /// How many files to download at once, shared between all in-flight requests.
+    pub concurrency: usize,
+    /// How many requests are handled at once. Websockets and event streams get their own thread.
+    pub workers: usize,
+    /// Ranges longer than this many days are generated in a background job. 0 disables jobs.
+    pub async_threshold_days: i64,
+    /// How long to keep a finished job's result, in seconds.
+    pub job_ttl: u64,
+    /// The refresh interval written to generated placefiles as `Refresh:`, in minutes.
+    pub refresh: u32,
+    pub colors: Colors,
+    pub icons: Option<Icons>,
+    pub live: Live,
+    pub cache: Cache,
+    pub publish: Vec<Publish>,
+    pub webhooks: Vec<Webhook>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            source: Source::Upstreams,
+            s3: S3Archive::default(),
+            upstreams: vec!["https://mesonet.agron.iastate.edu/archive/data".to_string()],
+            upstream_timeout: 30,
+            contact: None,
+            proxy: None,
+            local_archive: None,
+            storm_events: None,
+            product_api: "https://mesonet.agron.iastate.edu/api/1/nwstext".to_string(),
+            lsr_api: "https://mesonet.agron.iastate.edu/geojson/lsr.geojson".to_string(),
+            rate_limit: 10.,
+            burst: 10,
+            concurrency: 8,
+            workers: 16,
+            async_threshold_days: 31,
+            job_ttl: 3600,
+            refresh: 9999,
+            colors: Colors::default(),
+            icons: None,
+            live: Live::default(),
+            cache: Cache::default(),
+            publish: Vec::new(),
+            webhooks: Vec::new(),
+        }
+    }
+}
+
+/// Settings given on the command line, which take precedence over the config file.
+#[derive(Debug, Clone, Default)]
+pub struct Overrides {
+    pub concurrency: Option<usize>,
+    pub local_archive: Option<PathBuf>,
+}
+
+impl Overrides {
+    fn apply(&self, config: &mut Config) {
+        if let Some(concurrency) = self.concurrency {
+            config.concurrency = concurrency;
+        }
+        if let Some(dir) = &self.local_archive {
+            config.local_archive = Some(dir.clone());
+        }
+    }
+}
+
+impl Config {
+    /// Reads a config from a file, falling back to the defaults if there is no file.
+    fn load(path: &Path, overrides: &Overrides) -> Result<Self, String> {
+        let mut config: Self = match std::fs::read_to_string(path) {
+            Ok(text) => toml::from_str(&text).map_err(|e| e.to_string())?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Self::default(),
+            Err(e) => return Err(e.to_string()),
+        };
+
+        overrides.apply(&mut config);
+
+        // The upstreams are only read when neither a local archive nor a bucket replaces them.
+        let uses_upstreams = config.local_archive.is_none() && config.source != Source::S3;
+        if uses_upstreams && config.upstreams.is_empty() {
+            return Err("at least one upstream is required".to_string());
+        }
+
+        if config.source == Source::S3 {
+            if config.s3.bucket.is_empty() {
+                let error = "s3.bucket is required when source is s3, there is no public default";
+                return Err(error.to_string());
+            }
+
+            let items = chrono::format::StrftimeItems::new(&config.s3.key);
+            if items.into_iter().any(|item| item == chrono::format::Item::Error) {
+                return Err(format!("s3.key is not a valid strftime format: {}", config.s3.key));
+            }
+        }
+
+        if config.rate_limit.is_nan() || config.rate_limit < 0. {
+            return Err("rate_limit must not be negative".to_string());
+        }
+
+        if config.concurrency == 0 {
+            return Err("concurrency must be at least 1".to_string());
+        }
+
+        if config.workers == 0 {
+            return Err("workers must be at least 1".to_string());
+        }
+
+        if config.live.expired_grace < 0 {
+            return Err("live.expired_grace must not be negative".to_string());
+        }
+
+        if config.publish.iter().any(|target| target.url.is_empty()) {
+            return Err("every publish target needs a url".to_string());
+        }
+
+        for target in &config.publish {
+            if let Err(e) = crate::publish::query(target) {
+                return Err(format!("invalid query for publish target {}: {e}", target.url));
+            }
+        }
+
+        Ok(config)
+    }
+}
+
+lazy_static! {
+    static ref SOURCE: RwLock<(PathBuf, Overrides)> = RwLock::default();
+    static ref CURRENT: RwLock<Arc<Config>> = RwLock::new(Arc::new(Config::default()));
+}
+
+/// Returns a snapshot of the current config.
+///
+/// Requests should hold on to one snapshot, so a reload never changes the config halfway through.
+pub fn get() -> Arc<Config> {
+    CURRENT.read().unwrap().clone()
+}
+
+/// Loads the config for the first time.
+pub fn init(path: PathBuf, overrides: Overrides) -> Result<(), String> {
+    let config = Config::load(&path, &overrides)?;
+    *CURRENT.write().unwrap() = Arc::new(config);
+    *SOURCE.write().unwrap() = (path, overrides);
+    Ok(())
+}
+
+/// Reloads the config, keeping the old one if the file is invalid.
+pub fn reload() {
+    let (path, overrides) = SOURCE.read().unwrap().clone();
+    match Config::load(&path, &overrides) {
+        Ok(config) => {
+            crate::verify::load(&config);
+            *CURRENT.write().unwrap() = Arc::new(config);
+            // Cached responses were rendered with the old colors and settings.
+            crate::cache::clear();
+            crate::mvt::clear();
+            crate::live::clear();
+            println!("Reloaded config from {}", path.display());
+        }
+        Err(e) => eprintln!("Failed to reload config, keeping the old one: {e}"),
+    }
+}
+
+/// Reloads the config whenever the process receives a SIGHUP.
+#[cfg(unix)]
+pub fn watch_sighup() {
+    use signal_hook::{consts::SIGHUP, iterator::Signals};
+
+    let mut signals = Signals::new([SIGHUP]).expect("Failed to register SIGHUP handler");
+    std::thread::spawn(move || {
+        for _ in signals.forever() {
+            reload();
+        }
+    });
+}
+
+#[cfg(not(unix))]
+pub fn watch_sighup() {}
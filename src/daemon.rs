@@ -0,0 +1,94 @@
+use std::path::{Path, PathBuf};
+
+/// Reads the pid in a pidfile, if the file exists and holds one.
+fn read_pid(path: &Path) -> Option<i32> {
+    std::fs::read_to_string(path).ok()?.trim().parse().ok()
+}
+
+/// Tests if a process is still running.
+#[cfg(unix)]
+fn is_running(pid: i32) -> bool {
+    // Signal 0 only checks that the process exists and can be signalled.
+    unsafe { libc::kill(pid, 0) == 0 }
+}
+
+#[cfg(not(unix))]
+fn is_running(_pid: i32) -> bool {
+    false
+}
+
+/// Writes this process's pid to a pidfile, refusing if another running server already owns it.
+pub fn write_pidfile(path: &Path) -> Result<(), String> {
+    if let Some(pid) = read_pid(path).filter(|&pid| is_running(pid)) {
+        return Err(format!("already running with pid {pid}"));
+    }
+
+    std::fs::write(path, format!("{}\n", std::process::id())).map_err(|e| e.to_string())
+}
+
+/// Removes the pidfile and exits when the process is asked to stop, so init systems and
+/// service wrappers see a clean shutdown.
+#[cfg(unix)]
+pub fn exit_on_signal(pidfile: Option<PathBuf>) {
+    use signal_hook::{consts::SIGINT, consts::SIGTERM, iterator::Signals};
+
+    let mut signals = Signals::new([SIGTERM, SIGINT]).expect("Failed to register SIGTERM handler");
+    std::thread::spawn(move || {
+        if let Some(signal) = signals.forever().next() {
+            println!("Received signal {signal}, shutting down");
+            if let Some(path) = &pidfile {
+                let _ = std::fs::remove_file(path);
+            }
+            std::process::exit(0);
+        }
+    });
+}
+
+#[cfg(not(unix))]
+pub fn exit_on_signal(_pidfile: Option<PathBuf>) {}
+
+/// Opens where a daemon's output goes: the log file if one is given, or else nowhere.
+#[cfg(unix)]
+fn log_file(log: Option<&Path>) -> Result<std::fs::File, String> {
+    let path = log.unwrap_or(Path::new("/dev/null"));
+    std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|e| format!("failed to open {}: {e}", path.display()))
+}
+
+/// Detaches from the terminal and keeps running in the background.
+///
+/// This has to run before any threads are started, since only the calling thread survives a
+/// fork. Output is appended to the log file if one is given.
+#[cfg(unix)]
+pub fn daemonize(log: Option<&Path>) -> Result<(), String> {
+    use std::os::unix::io::AsRawFd;
+
+    let log = log_file(log)?;
+    let null = std::fs::File::open("/dev/null").map_err(|e| e.to_string())?;
+
+    unsafe {
+        // Fork twice, so the daemon is not a session leader and can never take a terminal again.
+        for _ in 0..2 {
+            match libc::fork() {
+                -1 => return Err(std::io::Error::last_os_error().to_string()),
+                0 => (),
+                _ => libc::_exit(0),
+            }
+            libc::setsid();
+        }
+
+        libc::dup2(null.as_raw_fd(), 0);
+        libc::dup2(log.as_raw_fd(), 1);
+        libc::dup2(log.as_raw_fd(), 2);
+    }
+
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn daemonize(_log: Option<&Path>) -> Result<(), String> {
+    Err("only supported on Unix, use a service wrapper such as NSSM instead".to_string())
+}
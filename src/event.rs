@@ -0,0 +1,223 @@
+use crate::geo;
+use crate::warning::Warning;
+use chrono::{DateTime, Datelike, Duration, Utc};
+use serde::Serialize;
+use std::collections::HashMap;
+
+/// Identifies a VTEC event: the office, phenomenon, event tracking number and year.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize)]
+pub struct EventKey {
+    pub office: String,
+    pub phenomenon: String,
+    pub etn: u32,
+    pub year: i32,
+}
+
+impl EventKey {
+    /// Finds the event a warning belongs to, if it has a VTEC string.
+    ///
+    /// The year is the one the event began in when the VTEC string gives it, and otherwise the
+    /// one the warning was issued in, which `among` corrects for events running past New Year.
+    pub fn of(warning: &Warning) -> Option<Self> {
+        warning.vtec.as_ref().map(|vtec| Self {
+            office: vtec.office.clone(),
+            phenomenon: vtec.phenomenon.clone(),
+            etn: vtec.etn,
+            year: vtec.begin.unwrap_or(warning.issued).year(),
+        })
+    }
+
+    /// Finds the event a warning belongs to, given the events already seen.
+    ///
+    /// An update issued in a new year belongs to the same number from the year before if that
+    /// event was seen, since numbers only start over for new events.
+    pub fn among(warning: &Warning, seen: impl Fn(&EventKey) -> bool) -> Option<Self> {
+        let key = Self::of(warning)?;
+        let is_new = warning.vtec.as_ref().is_some_and(|vtec| vtec.action == "NEW");
+        let year_before = Self {
+            year: key.year - 1,
+            ..key.clone()
+        };
+
+        match !is_new && !seen(&key) && seen(&year_before) {
+            true => Some(year_before),
+            false => Some(key),
+        }
+    }
+}
+
+/// Every product issued for one event, in the order they were issued.
+#[derive(Debug, Clone, Serialize)]
+pub struct Event {
+    pub key: Option<EventKey>,
+    pub warnings: Vec<Warning>,
+}
+
+impl Event {
+    pub fn first(&self) -> &Warning {
+        &self.warnings[0]
+    }
+
+    pub fn latest(&self) -> &Warning {
+        self.warnings.last().unwrap()
+    }
+
+    /// Serializes the event with its final expiration.
+    pub fn to_json(&self) -> String {
+        #[derive(Serialize)]
+        struct Json<'a> {
+            #[serde(flatten)]
+            event: &'a Event,
+            expires: Option<DateTime<Utc>>,
+        }
+
+        serde_json::to_string(&Json {
+            event: self,
+            expires: self.expires(),
+        })
+        .unwrap()
+    }
+
+    /// When the event finally ends, from the last product that gave an end time.
+    pub fn expires(&self) -> Option<DateTime<Utc>> {
+        self.warnings
+            .iter()
+            .rev()
+            .find_map(|warning| warning.vtec.as_ref()?.end)
+    }
+
+    /// When the event ended: when it was cancelled, expired or upgraded, or else its final
+    /// expiration.
+    pub fn ended(&self) -> Option<DateTime<Utc>> {
+        let latest = self.latest();
+        match latest.vtec.as_ref()?.action.as_str() {
+            "CAN" | "EXP" | "UPG" => Some(latest.issued),
+            _ => self.expires(),
+        }
+    }
+
+    /// The centroids of the event's polygons in the order they were issued, skipping repeats.
+    ///
+    /// This is a rough storm track, since the polygons follow the storm as it moves.
+    pub fn track(&self) -> Vec<(f32, f32)> {
+        let mut track: Vec<_> = self
+            .warnings
+            .iter()
+            .map(|warning| geo::centroid(&warning.polygon))
+            .collect();
+        track.dedup();
+        track
+    }
+}
+
+/// How long after its last product an event is assumed to be over.
+///
+/// Tornado warnings last an hour or two, so this leaves plenty of room for late updates.
+const EVENT_GAP: Duration = Duration::days(1);
+
+/// Groups warnings arriving in the order they were issued into events.
+///
+/// Events are handed to `each` once no more updates can arrive for them, so only the events
+/// around the current time are held in memory, no matter how long the range is.
+pub struct Grouper<F: FnMut(Event)> {
+    open: HashMap<EventKey, Event>,
+    each: F,
+}
+
+impl<F: FnMut(Event)> Grouper<F> {
+    pub fn new(each: F) -> Self {
+        Self {
+            open: HashMap::new(),
+            each,
+        }
+    }
+
+    /// Adds the next warning, finishing any events that ended well before it.
+    pub fn push(&mut self, warning: Warning) {
+        self.flush(Some(warning.issued - EVENT_GAP));
+
+        match EventKey::among(&warning, |key| self.open.contains_key(key)) {
+            Some(key) => self
+                .open
+                .entry(key.clone())
+                .or_insert_with(|| Event {
+                    key: Some(key),
+                    warnings: Vec::new(),
+                })
+                .warnings
+                .push(warning),
+            None => (self.each)(Event {
+                key: None,
+                warnings: vec![warning],
+            }),
+        }
+    }
+
+    /// Hands over the events last updated before `before`, or all of them, oldest first.
+    fn flush(&mut self, before: Option<DateTime<Utc>>) {
+        let mut done: Vec<_> = self
+            .open
+            .iter()
+            .filter(|(_, event)| before.is_none_or(|before| event.latest().issued < before))
+            .map(|(key, event)| (event.first().issued, key.clone()))
+            .collect();
+        done.sort();
+
+        for (_, key) in done {
+            let event = self.open.remove(&key).unwrap();
+            (self.each)(event);
+        }
+    }
+
+    /// Hands over the events that are still open.
+    pub fn finish(mut self) {
+        self.flush(None);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A warning product with the given WMO time and VTEC string.
+    fn warning(wmo_time: &str, vtec: &str) -> Warning {
+        Warning::parse(&format!(
+            "WFUS54 KOUN {wmo_time}\nTOROUN\nOKC017-010030-\n{vtec}\n\nTornado Warning\n\n\
+             LAT...LON 3545 9808 3561 9785 3552 9770 3534 9795\n\n$$\n"
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn events_keep_their_year_past_new_year() {
+        let new = warning("312350", "/O.NEW.KOUN.TO.W.0099.221231T2350Z-230101T0030Z/");
+        let con = warning("010010", "/O.CON.KOUN.TO.W.0099.000000T0000Z-230101T0030Z/");
+        assert_eq!(con.issued.year(), 2023);
+        assert_eq!(EventKey::of(&new).unwrap().year, 2022);
+
+        let mut events = Vec::new();
+        let mut grouper = Grouper::new(|event| events.push(event));
+        grouper.push(new);
+        grouper.push(con);
+        grouper.finish();
+
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].key.as_ref().unwrap().year, 2022);
+        assert_eq!(events[0].warnings.len(), 2);
+    }
+
+    #[test]
+    fn new_events_reusing_a_number_stay_apart() {
+        let old = warning("312350", "/O.NEW.KOUN.TO.W.0001.221231T2350Z-230101T0030Z/");
+        let new = warning("010015", "/O.NEW.KOUN.TO.W.0001.230101T0015Z-230101T0100Z/");
+
+        let mut events = Vec::new();
+        let mut grouper = Grouper::new(|event| events.push(event));
+        grouper.push(old);
+        grouper.push(new);
+        grouper.finish();
+
+        let years: Vec<_> = events.iter().map(|event| event.key.as_ref().unwrap().year).collect();
+        assert_eq!(years, [2022, 2023]);
+    }
+}
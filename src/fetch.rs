@@ -0,0 +1,362 @@
+use crate::config::{Config, S3Archive, Source};
+use crate::verify::{self, Report};
+use crate::{HttpError, HttpResult};
+use chrono::{Datelike, NaiveDate, NaiveTime};
+use headers::{Authorization, HeaderMapExt, ProxyAuthorization};
+use hyper::header::USER_AGENT;
+use hyper::{body, client::HttpConnector, Body, Client, HeaderMap, Request, StatusCode, Uri};
+use hyper_proxy::{Intercept, Proxy, ProxyConnector};
+use hyper_tls::HttpsConnector;
+use lazy_static::lazy_static;
+use percent_encoding::percent_decode_str;
+use std::io;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// Where the program can be found, for the User-Agent.
+const REPOSITORY: &str = "https://github.com/SuperWinner50/tors_placefile";
+
+pub type HttpsClient = Client<ProxyConnector<HttpsConnector<HttpConnector>>>;
+
+/// Finds the proxy for a scheme, from the config or else the usual environment variables.
+fn proxy_url(config: &Config, scheme: &str) -> Option<String> {
+    let names = match scheme {
+        "https" => ["HTTPS_PROXY", "https_proxy"],
+        _ => ["HTTP_PROXY", "http_proxy"],
+    };
+
+    config.proxy.clone().or_else(|| {
+        names
+            .iter()
+            .find_map(|name| std::env::var(name).ok())
+            .filter(|url| !url.is_empty())
+    })
+}
+
+/// Builds a proxy from its url, sending any `user:password@` in it as basic credentials.
+///
+/// Credentials are only sent while opening a tunnel, so plain http is tunnelled too when they are
+/// given.
+fn proxy(intercept: Intercept, url: &str) -> Result<Proxy, String> {
+    let (scheme, rest) = url.split_once("://").unwrap_or(("http", url));
+    let (credentials, host) = match rest.rsplit_once('@') {
+        Some((credentials, host)) => (Some(credentials), host),
+        None => (None, rest),
+    };
+
+    let uri: Uri = format!("{scheme}://{host}")
+        .parse()
+        .map_err(|e| format!("{e}"))?;
+    let mut proxy = Proxy::new(intercept, uri);
+
+    if let Some(credentials) = credentials {
+        let (user, password) = credentials.split_once(':').unwrap_or((credentials, ""));
+        let decode = |s| percent_decode_str(s).decode_utf8_lossy().into_owned();
+        let mut headers = HeaderMap::new();
+        headers.typed_insert(ProxyAuthorization(
+            Authorization::basic(&decode(user), &decode(password)).0,
+        ));
+        for (name, value) in headers {
+            proxy.set_header(name.unwrap(), value);
+        }
+        proxy.force_connect();
+    }
+
+    Ok(proxy)
+}
+
+/// Creates a client for talking to the upstreams, through a proxy if one is configured.
+pub fn client(config: &Config) -> HttpsClient {
+    let mut connector = ProxyConnector::new(HttpsConnector::new()).unwrap();
+
+    for (scheme, intercept) in [("http", Intercept::Http), ("https", Intercept::Https)] {
+        if let Some(url) = proxy_url(config, scheme) {
+            match proxy(intercept, &url) {
+                Ok(proxy) => connector.add_proxy(proxy),
+                Err(e) => eprintln!("Ignoring invalid {scheme} proxy: {e}"),
+            }
+        }
+    }
+
+    Client::builder().build(connector)
+}
+
+/// Builds the url of the tornado warning file for one day.
+fn day_url(base: &str, date: NaiveDate) -> String {
+    format!(
+        "{base}/{y}/{m:0>2}/{d:0>2}/text/noaaport/TOR_{y}{m:0>2}{d:0>2}.txt",
+        base = base.trim_end_matches('/'),
+        y = date.year(),
+        m = date.month(),
+        d = date.day()
+    )
+}
+
+/// Decodes text as utf-8, replacing stray bytes instead of rejecting the whole file.
+fn decode_lossy(bytes: Vec<u8>) -> String {
+    String::from_utf8(bytes).unwrap_or_else(|e| String::from_utf8_lossy(e.as_bytes()).into_owned())
+}
+
+/// Converts line endings to `\n` and removes control characters such as SOH and ETX.
+///
+/// Noaaport products end their lines with `\r\r\n`, which is one line ending, not two. Text that
+/// is already clean is returned as is, without copying it.
+fn normalize(text: String) -> String {
+    let is_noise = |c: char| c.is_control() && c != '\n' && c != '\t';
+    if !text.contains(is_noise) {
+        return text;
+    }
+
+    text.replace("\r\r\n", "\n")
+        .replace("\r\n", "\n")
+        .replace('\r', "\n")
+        .replace(is_noise, "")
+}
+
+/// Builds the User-Agent sent upstream, so the archive operators know who to contact.
+fn user_agent(config: &Config) -> String {
+    let name = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
+    match &config.contact {
+        Some(contact) => format!("{name} (+{REPOSITORY}; {contact})"),
+        None => format!("{name} (+{REPOSITORY})"),
+    }
+}
+
+/// A token bucket shared by every upstream request in the server.
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+lazy_static! {
+    // The bucket starts full, and is capped to the burst size on first use.
+    static ref BUCKET: Mutex<Bucket> = Mutex::new(Bucket {
+        tokens: f64::INFINITY,
+        updated: Instant::now(),
+    });
+}
+
+/// Waits for a turn to send a request upstream, according to the configured rate limit.
+///
+/// Taking a token can leave the bucket negative, which reserves a later turn, so waiting requests
+/// are let through in the order they arrived.
+async fn throttle(config: &Config) {
+    if config.rate_limit <= 0. {
+        return;
+    }
+
+    let wait = {
+        let mut bucket = BUCKET.lock().unwrap();
+        let now = Instant::now();
+        let refill = now.duration_since(bucket.updated).as_secs_f64() * config.rate_limit;
+        bucket.tokens = (bucket.tokens + refill).min(config.burst.max(1) as f64) - 1.;
+        bucket.updated = now;
+        (bucket.tokens < 0.).then(|| Duration::from_secs_f64(-bucket.tokens / config.rate_limit))
+    };
+
+    if let Some(wait) = wait {
+        tokio::time::sleep(wait).await;
+    }
+}
+
+/// The download slots shared by every request in the server.
+struct Slots {
+    semaphore: Arc<Semaphore>,
+    /// How many slots there are, free or taken.
+    size: usize,
+}
+
+lazy_static! {
+    static ref SLOTS: Mutex<Slots> = Mutex::new(Slots {
+        semaphore: Arc::new(Semaphore::new(0)),
+        size: 0,
+    });
+}
+
+/// Waits for one of the `concurrency` download slots, which all requests share no matter how many
+/// there are, resizing them first if the config changed.
+async fn slot(config: &Config) -> OwnedSemaphorePermit {
+    let semaphore = {
+        let mut slots = SLOTS.lock().unwrap();
+        if slots.size < config.concurrency {
+            slots.semaphore.add_permits(config.concurrency - slots.size);
+            slots.size = config.concurrency;
+        } else if slots.size > config.concurrency {
+            // Only free slots can be removed, so the rest go as their downloads finish.
+            slots.size -= slots.semaphore.forget_permits(slots.size - config.concurrency);
+        }
+        slots.semaphore.clone()
+    };
+
+    // The semaphore is never closed.
+    semaphore.acquire_owned().await.unwrap()
+}
+
+/// Fetches a url as text. A missing file is not an error, since days without warnings have no file.
+async fn fetch(client: &HttpsClient, config: &Config, url: &str) -> HttpResult<String> {
+    let _slot = slot(config).await;
+    throttle(config).await;
+
+    let request = async {
+        let req = Request::get(url.parse::<Uri>().map_err(HttpError::ParseError)?)
+            .header(USER_AGENT, user_agent(config))
+            .body(Body::empty())
+            .map_err(HttpError::BuildError)?;
+        let res = client.request(req).await.map_err(HttpError::GetError)?;
+
+        match res.status() {
+            StatusCode::NOT_FOUND => return Ok(String::new()),
+            status if !status.is_success() => return Err(HttpError::StatusError(status)),
+            _ => (),
+        }
+
+        // Converting the bytes into a vec reuses their buffer when possible, instead of copying it.
+        let bytes: Vec<u8> = body::to_bytes(res)
+            .await
+            .map_err(HttpError::ToBytesError)?
+            .into();
+
+        Ok(normalize(decode_lossy(bytes)))
+    };
+
+    tokio::time::timeout(Duration::from_secs(config.upstream_timeout), request)
+        .await
+        .map_err(|_| HttpError::Timeout)?
+}
+
+/// Fetches a single product by its id, such as `202205012245-KOUN-WFUS54-TOROUN`.
+pub async fn fetch_product(
+    client: &HttpsClient,
+    config: &Config,
+    product: &str,
+) -> HttpResult<String> {
+    let url = format!("{}/{product}", config.product_api.trim_end_matches('/'));
+    let text = fetch(client, config, &url).await?;
+
+    match text.trim().is_empty() {
+        true => Err(HttpError::NotFound),
+        false => Ok(text),
+    }
+}
+
+/// Fetches the tornado Local Storm Reports that could fall inside warnings issued on a day.
+///
+/// Reports up to six hours into the next day are included, for warnings issued late in the day.
+pub async fn fetch_lsrs(
+    client: &HttpsClient,
+    config: &Config,
+    date: NaiveDate,
+) -> HttpResult<Vec<Report>> {
+    let start = date.and_time(NaiveTime::MIN).and_utc();
+    let end = start + chrono::Duration::hours(30);
+    let url = format!(
+        "{}?sts={}&ets={}",
+        config.lsr_api,
+        start.format("%Y-%m-%dT%H:%MZ"),
+        end.format("%Y-%m-%dT%H:%MZ")
+    );
+
+    let json = fetch(client, config, &url).await?;
+    if json.trim().is_empty() {
+        return Ok(Vec::new());
+    }
+
+    verify::parse_lsrs(&json).map_err(HttpError::ReportError)
+}
+
+/// Builds the url of a day's file in a public S3 bucket.
+fn s3_url(s3: &S3Archive, date: NaiveDate) -> String {
+    let key = date.format(&s3.key);
+    match &s3.endpoint {
+        Some(endpoint) => format!("{}/{}/{key}", endpoint.trim_end_matches('/'), s3.bucket),
+        None => format!("https://{}.s3.{}.amazonaws.com/{key}", s3.bucket, s3.region),
+    }
+}
+
+/// Reads a day's file from a local archive, cleaned up like a downloaded one.
+pub fn read_file(path: &Path) -> io::Result<String> {
+    std::fs::read(path).map(|bytes| normalize(decode_lossy(bytes)))
+}
+
+/// Reads the tornado warnings for one day from a local archive.
+///
+/// Both a mirror of the upstream layout and a flat directory of `TOR_YYYYMMDD.txt` files work. A
+/// missing file is not an error, like a missing upstream file.
+async fn read_day(dir: &Path, date: NaiveDate) -> HttpResult<String> {
+    let name = format!("TOR_{}.txt", date.format("%Y%m%d"));
+    let paths = [
+        dir.join(date.format("%Y/%m/%d/text/noaaport").to_string())
+            .join(&name),
+        dir.join(&name),
+    ];
+
+    let read = move || {
+        for path in paths {
+            match read_file(&path) {
+                Ok(text) => return Ok(text),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(HttpError::ReadError(e)),
+            }
+        }
+
+        Ok(String::new())
+    };
+
+    tokio::task::spawn_blocking(read).await.unwrap()
+}
+
+/// Fetches the tornado warnings for one day, falling back through the upstreams in order.
+///
+/// A day missing from one upstream is looked for in the next, since mirrors can lag behind or
+/// have gaps. It is only empty when every upstream answered that it doesn't have it, and otherwise
+/// the first upstream to fail gives the error, since it might have had the day.
+pub async fn fetch_day(
+    client: &HttpsClient,
+    config: &Config,
+    date: NaiveDate,
+    id: &str,
+) -> HttpResult<String> {
+    if let Some(dir) = &config.local_archive {
+        return read_day(dir, date).await;
+    }
+
+    if config.source == Source::S3 {
+        let result = fetch(client, config, &s3_url(&config.s3, date)).await;
+        if let Err(e) = &result {
+            eprintln!("[{id}] S3 bucket {} failed for {date}: {e}", config.s3.bucket);
+        }
+        return result;
+    }
+
+    let mut error = None;
+
+    for base in &config.upstreams {
+        match fetch(client, config, &day_url(base, date)).await {
+            Ok(text) if text.is_empty() => (),
+            Ok(text) => return Ok(text),
+            Err(e) => {
+                eprintln!("[{id}] Upstream {base} failed for {date}: {e}");
+                error.get_or_insert(e);
+            }
+        }
+    }
+
+    match error {
+        Some(e) => Err(e),
+        None => Ok(String::new()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn line_endings_and_control_characters() {
+        let text = "\u{1}\r\r\n000 \r\r\nWFUS54 KOUN 202102\r\nTORTSA\rLINE\t1\u{3}".to_string();
+        assert_eq!(normalize(text), "\n000 \nWFUS54 KOUN 202102\nTORTSA\nLINE\t1");
+        assert_eq!(normalize("clean\ntext\n".to_string()), "clean\ntext\n");
+    }
+}
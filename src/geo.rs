@@ -0,0 +1,383 @@
+/// Finds the centroid of a closed polygon's vertices.
+pub fn centroid(polygon: &[(f32, f32)]) -> (f32, f32) {
+    // Skip the closing point so the first vertex isn't counted twice.
+    let vertices = &polygon[..polygon.len().saturating_sub(1).max(1)];
+    let (lat, lon) = vertices
+        .iter()
+        .fold((0., 0.), |(lat, lon), (y, x)| (lat + y, lon + x));
+    let n = vertices.len() as f32;
+
+    (lat / n, lon / n)
+}
+
+/// The radius of the sphere used by Web Mercator, in meters.
+const EARTH_RADIUS: f64 = 6_378_137.;
+/// The mean radius of the Earth, in kilometers, for great-circle distances.
+const MEAN_RADIUS_KM: f64 = 6371.0088;
+
+/// Projects a (lat, lon) point to Web Mercator (EPSG:3857) x and y, in meters.
+pub fn mercator((lat, lon): (f32, f32)) -> (f64, f64) {
+    let (lat, lon) = (f64::from(lat).to_radians(), f64::from(lon).to_radians());
+    let y = (std::f64::consts::FRAC_PI_4 + lat / 2.).tan().ln();
+
+    (EARTH_RADIUS * lon, EARTH_RADIUS * y)
+}
+
+/// Projects a (lat, lon) point to Web Mercator scaled to 0 to 1 across the world, with y growing
+/// southward like tile coordinates.
+pub fn world(point: (f32, f32)) -> (f64, f64) {
+    let (x, y) = mercator(point);
+    let size = 2. * std::f64::consts::PI * EARTH_RADIUS;
+
+    (0.5 + x / size, 0.5 - y / size)
+}
+
+/// The distance from a point to the segment between `a` and `b`, in degrees.
+fn segment_distance(p: (f32, f32), a: (f32, f32), b: (f32, f32)) -> f32 {
+    let (dy, dx) = (b.0 - a.0, b.1 - a.1);
+    let length = dy * dy + dx * dx;
+    let t = if length == 0. {
+        0.
+    } else {
+        (((p.0 - a.0) * dy + (p.1 - a.1) * dx) / length).clamp(0., 1.)
+    };
+
+    (p.0 - (a.0 + t * dy)).hypot(p.1 - (a.1 + t * dx))
+}
+
+/// Marks the points between `first` and `last` that Douglas–Peucker keeps.
+fn douglas_peucker(
+    path: &[(f32, f32)],
+    first: usize,
+    last: usize,
+    tolerance: f32,
+    keep: &mut [bool],
+) {
+    let farthest = (first + 1..last)
+        .map(|i| (i, segment_distance(path[i], path[first], path[last])))
+        .max_by(|a, b| a.1.total_cmp(&b.1));
+
+    if let Some((i, _)) = farthest.filter(|&(_, distance)| distance > tolerance) {
+        keep[i] = true;
+        douglas_peucker(path, first, i, tolerance, keep);
+        douglas_peucker(path, i, last, tolerance, keep);
+    }
+}
+
+/// Simplifies a closed polygon with Douglas–Peucker, dropping vertices closer than `tolerance`
+/// degrees to the simplified outline.
+///
+/// Douglas–Peucker doesn't preserve topology, so the polygon is left alone if simplifying would
+/// collapse it below a triangle or make its edges cross, which `triangulate` can't handle.
+pub fn simplify(polygon: &[(f32, f32)], tolerance: f32) -> Vec<(f32, f32)> {
+    if polygon.len() <= 4 {
+        return polygon.to_vec();
+    }
+
+    // A closed ring starts and ends on the same point, so split it at the vertex farthest from
+    // the start too, otherwise the whole ring would be measured against a zero-length segment.
+    let last = polygon.len() - 1;
+    let (lat, lon) = polygon[0];
+    let far = (1..last)
+        .max_by(|&a, &b| {
+            let distance = |i: usize| (polygon[i].0 - lat).hypot(polygon[i].1 - lon);
+            distance(a).total_cmp(&distance(b))
+        })
+        .unwrap();
+
+    let mut keep = vec![false; polygon.len()];
+    (keep[0], keep[far], keep[last]) = (true, true, true);
+    douglas_peucker(polygon, 0, far, tolerance, &mut keep);
+    douglas_peucker(polygon, far, last, tolerance, &mut keep);
+
+    let mut simplified: Vec<_> = polygon
+        .iter()
+        .zip(keep)
+        .filter_map(|(&point, keep)| keep.then_some(point))
+        .collect();
+
+    match validate(&mut simplified) {
+        Ok(()) => simplified,
+        Err(_) => polygon.to_vec(),
+    }
+}
+
+/// Which side of the line through `a` and `b` a point is on: positive, negative or 0 if on it.
+fn orientation(a: (f32, f32), b: (f32, f32), p: (f32, f32)) -> f32 {
+    (b.1 - a.1) * (p.0 - a.0) - (b.0 - a.0) * (p.1 - a.1)
+}
+
+/// Tests if the segments `a`-`b` and `c`-`d` cross each other.
+fn segments_cross(a: (f32, f32), b: (f32, f32), c: (f32, f32), d: (f32, f32)) -> bool {
+    let (d1, d2) = (orientation(a, b, c), orientation(a, b, d));
+    let (d3, d4) = (orientation(c, d, a), orientation(c, d, b));
+
+    d1 * d2 < 0. && d3 * d4 < 0.
+}
+
+/// Checks a polygon, repairing what can be repaired and explaining what can't.
+///
+/// Repeated vertices are dropped and an open ring is closed. Rings with fewer than three distinct
+/// vertices, coordinates off the globe, or crossing edges, usually from a typo in the `LAT...LON`
+/// line, are rejected.
+pub fn validate(polygon: &mut Vec<(f32, f32)>) -> Result<(), String> {
+    if let Some(&(lat, lon)) = polygon
+        .iter()
+        .find(|(lat, lon)| !(-90. ..=90.).contains(lat) || !(-180. ..=180.).contains(lon))
+    {
+        return Err(format!("vertex {lat}, {lon} is off the globe"));
+    }
+
+    polygon.dedup();
+    if polygon.first() != polygon.last() {
+        polygon.push(polygon[0]);
+    }
+
+    // A closed triangle has four points, the last repeating the first.
+    if polygon.len() < 4 {
+        return Err(format!(
+            "only {} distinct vertices",
+            polygon.len().saturating_sub(1)
+        ));
+    }
+
+    let edges = polygon.len() - 1;
+    for i in 0..edges {
+        // Skip the neighbouring edges, which always share a vertex with this one.
+        for j in i + 2..edges {
+            if i == 0 && j == edges - 1 {
+                continue;
+            }
+            let (a, b, c, d) = (polygon[i], polygon[i + 1], polygon[j], polygon[j + 1]);
+            if segments_cross(a, b, c, d) {
+                return Err(format!("edges {i} and {j} cross"));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Splits a closed polygon into triangles by clipping ears, for drawing it filled.
+///
+/// The polygon must not cross itself, which `validate` makes sure of.
+pub fn triangulate(polygon: &[(f32, f32)]) -> Vec<[(f32, f32); 3]> {
+    let mut points = polygon[..polygon.len() - 1].to_vec();
+    // Ears are found by turning the same way as the polygon winds, so wind it one way.
+    let winding: f32 = (1..points.len().saturating_sub(1))
+        .map(|i| orientation(points[0], points[i], points[i + 1]))
+        .sum();
+    if winding < 0. {
+        points.reverse();
+    }
+
+    let mut triangles = Vec::with_capacity(points.len().saturating_sub(2));
+    while points.len() > 3 {
+        let n = points.len();
+        let corner = |i: usize| (points[(i + n - 1) % n], points[i], points[(i + 1) % n]);
+        let ear = (0..n).find(|&i| {
+            let (a, b, c) = corner(i);
+            orientation(a, b, c) > 0.
+                && !points.iter().any(|&p| {
+                    p != a
+                        && p != b
+                        && p != c
+                        && orientation(a, b, p) >= 0.
+                        && orientation(b, c, p) >= 0.
+                        && orientation(c, a, p) >= 0.
+                })
+        });
+
+        // Only degenerate leftovers, such as collinear points, have no ears.
+        let Some(i) = ear else { return triangles };
+        let (a, b, c) = corner(i);
+        triangles.push([a, b, c]);
+        points.remove(i);
+    }
+
+    if let [a, b, c] = points[..] {
+        triangles.push([a, b, c]);
+    }
+
+    triangles
+}
+
+/// Tests if a point is inside a closed polygon, by counting the edges a ray from it crosses.
+pub fn contains(polygon: &[(f32, f32)], (lat, lon): (f32, f32)) -> bool {
+    let mut inside = false;
+
+    for edge in polygon.windows(2) {
+        let ((lat0, lon0), (lat1, lon1)) = (edge[0], edge[1]);
+        if (lat0 > lat) != (lat1 > lat) {
+            let crossing = lon0 + (lat - lat0) / (lat1 - lat0) * (lon1 - lon0);
+            if lon < crossing {
+                inside = !inside;
+            }
+        }
+    }
+
+    inside
+}
+
+/// A polygon that may have holes, as closed rings of (lat, lon) pairs with its outline first.
+pub type Area = Vec<Vec<(f32, f32)>>;
+
+/// Tests if a closed polygon and an area overlap at all.
+///
+/// They overlap if a vertex of one is inside the other, or their edges cross. A polygon entirely
+/// within one of the area's holes has neither.
+pub fn intersects(polygon: &[(f32, f32)], area: &[Vec<(f32, f32)>]) -> bool {
+    let Some((outline, holes)) = area.split_first() else {
+        return false;
+    };
+    let in_area = |&point: &(f32, f32)| {
+        contains(outline, point) && !holes.iter().any(|hole| contains(hole, point))
+    };
+
+    polygon.iter().any(in_area)
+        || outline.iter().any(|&point| contains(polygon, point))
+        || polygon.windows(2).any(|edge| {
+            area.iter()
+                .flat_map(|ring| ring.windows(2))
+                .any(|other| segments_cross(edge[0], edge[1], other[0], other[1]))
+        })
+}
+
+/// A (lat, lon) point in radians.
+fn radians((lat, lon): (f32, f32)) -> (f64, f64) {
+    (f64::from(lat).to_radians(), f64::from(lon).to_radians())
+}
+
+/// The angle along a great circle between two points, in radians, by the haversine formula.
+fn central_angle(a: (f32, f32), b: (f32, f32)) -> f64 {
+    let ((lat1, lon1), (lat2, lon2)) = (radians(a), radians(b));
+    let h = ((lat2 - lat1) / 2.).sin().powi(2)
+        + lat1.cos() * lat2.cos() * ((lon2 - lon1) / 2.).sin().powi(2);
+
+    2. * h.sqrt().min(1.).asin()
+}
+
+/// The initial bearing of the great circle from one point to another, in radians.
+fn bearing(a: (f32, f32), b: (f32, f32)) -> f64 {
+    let ((lat1, lon1), (lat2, lon2)) = (radians(a), radians(b));
+    let y = (lon2 - lon1).sin() * lat2.cos();
+    let x = lat1.cos() * lat2.sin() - lat1.sin() * lat2.cos() * (lon2 - lon1).cos();
+
+    y.atan2(x)
+}
+
+/// The great-circle distance between two points, in kilometers.
+fn great_circle(a: (f32, f32), b: (f32, f32)) -> f64 {
+    central_angle(a, b) * MEAN_RADIUS_KM
+}
+
+/// The great-circle distance from a point to the nearest point of the arc between `a` and `b`, in
+/// kilometers.
+fn great_circle_to_segment(p: (f32, f32), a: (f32, f32), b: (f32, f32)) -> f64 {
+    let to_p = central_angle(a, p);
+    let turn = bearing(a, p) - bearing(a, b);
+    // The point is behind `a`, so `a` is the nearest.
+    if turn.cos() <= 0. {
+        return to_p * MEAN_RADIUS_KM;
+    }
+
+    let cross_track = (to_p.sin() * turn.sin()).asin();
+    let along_track = (to_p.cos() / cross_track.cos()).clamp(-1., 1.).acos();
+    match along_track > central_angle(a, b) {
+        true => great_circle(p, b),
+        false => cross_track.abs() * MEAN_RADIUS_KM,
+    }
+}
+
+/// The great-circle distance from a point to a closed polygon, in kilometers, which is 0 inside it.
+pub fn great_circle_to_polygon(point: (f32, f32), polygon: &[(f32, f32)]) -> f64 {
+    if contains(polygon, point) {
+        return 0.;
+    }
+
+    polygon
+        .windows(2)
+        .map(|edge| great_circle_to_segment(point, edge[0], edge[1]))
+        .fold(f64::INFINITY, f64::min)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A closed square from 35 to 36 degrees north and 97 to 98 degrees west.
+    const SQUARE: [(f32, f32); 5] =
+        [(35., -98.), (36., -98.), (36., -97.), (35., -97.), (35., -98.)];
+
+    /// The area of a triangle, in square degrees.
+    fn area([a, b, c]: [(f32, f32); 3]) -> f32 {
+        orientation(a, b, c).abs() / 2.
+    }
+
+    #[test]
+    fn validate_repairs_rings() {
+        let mut polygon = vec![(35., -98.), (36., -98.), (36., -98.), (36., -97.), (35., -97.)];
+        validate(&mut polygon).unwrap();
+
+        assert_eq!(polygon, SQUARE);
+    }
+
+    #[test]
+    fn validate_rejects_broken_rings() {
+        let off_globe = vec![(35., -98.), (36., -198.), (36., -97.)];
+        assert!(validate(&mut off_globe.clone()).is_err());
+
+        let line = vec![(35., -98.), (36., -98.), (35., -98.)];
+        assert_eq!(validate(&mut line.clone()).unwrap_err(), "only 2 distinct vertices");
+
+        let bowtie = vec![(36., -95.5), (36.1, -95.4), (36., -95.4), (36.1, -95.5)];
+        assert_eq!(validate(&mut bowtie.clone()).unwrap_err(), "edges 0 and 2 cross");
+    }
+
+    #[test]
+    fn triangulate_convex_and_concave_rings() {
+        let triangles = triangulate(&SQUARE);
+        assert_eq!(triangles.len(), 2);
+        assert_eq!(triangles.into_iter().map(area).sum::<f32>(), 1.);
+
+        // An L shape, wound the other way, with a reflex corner.
+        let l_shape = [(0., 0.), (0., 2.), (1., 2.), (1., 1.), (2., 1.), (2., 0.), (0., 0.)];
+        let triangles = triangulate(&l_shape);
+        assert_eq!(triangles.len(), 4);
+        assert_eq!(triangles.into_iter().map(area).sum::<f32>(), 3.);
+    }
+
+    #[test]
+    fn contains_points() {
+        assert!(contains(&SQUARE, (35.5, -97.5)));
+        assert!(!contains(&SQUARE, (36.5, -97.5)));
+        assert!(!contains(&SQUARE, (35.5, -96.5)));
+    }
+
+    #[test]
+    fn great_circle_distances() {
+        assert_eq!(great_circle_to_polygon((35.5, -97.5), &SQUARE), 0.);
+
+        // A degree of latitude is about 111.2 km anywhere, less the little the great circle along
+        // the edge bulges north of its parallel.
+        let north = great_circle_to_polygon((37., -97.5), &SQUARE);
+        assert!((north - 111.2).abs() < 0.2, "{north}");
+
+        // Past a corner, the corner is the nearest point.
+        let corner = great_circle_to_polygon((34., -99.), &SQUARE);
+        assert!((corner - great_circle((34., -99.), (35., -98.))).abs() < 1e-6);
+
+        // A degree of longitude shrinks with the cosine of the latitude.
+        let east = great_circle_to_polygon((35.5, -96.), &SQUARE);
+        let expected = 111.195 * 35.5f64.to_radians().cos();
+        assert!((east - expected).abs() < 0.2, "{east} {expected}");
+    }
+
+    #[test]
+    fn simplify_keeps_rings_that_would_cross() {
+        let ring = [(5., 9.), (8., 3.), (8., 6.), (7., 5.), (6., 5.), (0., 8.), (5., 9.)];
+        assert!(validate(&mut ring.to_vec()).is_ok());
+
+        assert_eq!(simplify(&ring, 1.5), ring);
+    }
+}
@@ -0,0 +1,250 @@
+use crate::config::Style;
+use crate::event::{Event, EventKey};
+use crate::geo;
+use crate::product::Vtec;
+use crate::query::Crs;
+use crate::warning::{Severity, Warning};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::Value;
+use std::io::Write;
+
+/// A line style as simplestyle properties, which web maps such as geojson.io draw with.
+#[derive(Serialize)]
+struct Stroke {
+    /// The color as `#rrggbb`.
+    stroke: String,
+    #[serde(rename = "stroke-width")]
+    stroke_width: f32,
+}
+
+impl Stroke {
+    fn new(style: &Style) -> Self {
+        let hex: String = style
+            .color
+            .split_whitespace()
+            .take(3)
+            .map(|channel| format!("{:02x}", channel.parse::<u8>().unwrap_or(0)))
+            .collect();
+
+        Self {
+            stroke: format!("#{hex}"),
+            stroke_width: style.width,
+        }
+    }
+}
+
+/// The properties written for each warning.
+#[derive(Serialize)]
+struct Properties<'a> {
+    issued: DateTime<Utc>,
+    severity: Severity,
+    observed: bool,
+    waterspout: bool,
+    verified: Option<bool>,
+    source: &'a Option<String>,
+    max_hail_size: Option<f32>,
+    max_wind_gust: Option<u32>,
+    counties: &'a [String],
+    vtec: &'a Option<Vtec>,
+    /// The range the warning was found in, when several were asked for.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    range: Option<String>,
+    #[serde(flatten)]
+    stroke: Option<Stroke>,
+}
+
+/// Starts a feature collection, naming its CRS if it isn't the default WGS84.
+pub fn header(crs: Crs) -> Vec<u8> {
+    let mut writer = Vec::new();
+    write!(writer, "{{\"type\":\"FeatureCollection\",").unwrap();
+    if crs != Crs::Wgs84 {
+        write!(
+            writer,
+            "\"crs\":{{\"type\":\"name\",\"properties\":{{\"name\":\"{}\"}}}},",
+            crs.urn()
+        )
+        .unwrap();
+    }
+    write!(writer, "\"features\":[").unwrap();
+
+    writer
+}
+
+/// Writes a list of (lat, lon) points as GeoJSON positions in the given CRS.
+fn coordinates(writer: &mut Vec<u8>, points: &[(f32, f32)], crs: Crs) {
+    writer.push(b'[');
+    for (i, &point) in points.iter().enumerate() {
+        if i > 0 {
+            writer.push(b',');
+        }
+        // Coordinates are written by hand, since serde_json would widen the f32s to noisy f64s.
+        match crs {
+            Crs::Wgs84 => write!(writer, "[{},{}]", point.1, point.0).unwrap(),
+            Crs::WebMercator => {
+                let (x, y) = geo::mercator(point);
+                write!(writer, "[{x:.1},{y:.1}]").unwrap()
+            }
+        }
+    }
+    writer.push(b']');
+}
+
+/// Writes one warning as a polygon feature, after a comma unless it is the first.
+///
+/// The style is written as simplestyle properties if one is given.
+pub fn feature(
+    writer: &mut Vec<u8>,
+    warning: &Warning,
+    style: Option<&Style>,
+    range: Option<String>,
+    crs: Crs,
+    first: bool,
+) {
+    if !first {
+        writer.push(b',');
+    }
+
+    writer.extend_from_slice(br#"{"type":"Feature","geometry":{"type":"Polygon","coordinates":["#);
+    coordinates(writer, &warning.polygon, crs);
+    writer.extend_from_slice(br#"]},"properties":"#);
+
+    let properties = Properties {
+        issued: warning.issued,
+        severity: warning.severity,
+        observed: warning.observed,
+        waterspout: warning.waterspout,
+        verified: warning.verified,
+        source: &warning.source,
+        max_hail_size: warning.max_hail_size,
+        max_wind_gust: warning.max_wind_gust,
+        counties: &warning.counties,
+        vtec: &warning.vtec,
+        range,
+        stroke: style.map(Stroke::new),
+    };
+    serde_json::to_writer(&mut *writer, &properties).unwrap();
+    writer.push(b'}');
+}
+
+/// Ends a feature collection.
+pub fn footer(writer: &mut Vec<u8>) {
+    writer.extend_from_slice(b"]}\n");
+}
+
+/// The properties written for each storm track.
+#[derive(Serialize)]
+struct TrackProperties<'a> {
+    event: &'a Option<EventKey>,
+    issued: DateTime<Utc>,
+    expires: Option<DateTime<Utc>>,
+    products: usize,
+    #[serde(flatten)]
+    stroke: Option<Stroke>,
+}
+
+/// Writes an event's storm track as a line feature, after a comma unless it is the first.
+pub fn track(
+    writer: &mut Vec<u8>,
+    event: &Event,
+    track: &[(f32, f32)],
+    style: Option<&Style>,
+    crs: Crs,
+    first: bool,
+) {
+    if !first {
+        writer.push(b',');
+    }
+
+    writer
+        .extend_from_slice(br#"{"type":"Feature","geometry":{"type":"LineString","coordinates":"#);
+    coordinates(writer, track, crs);
+    writer.extend_from_slice(br#"},"properties":"#);
+
+    let properties = TrackProperties {
+        event: &event.key,
+        issued: event.first().issued,
+        expires: event.expires(),
+        products: event.warnings.len(),
+        stroke: style.map(Stroke::new),
+    };
+    serde_json::to_writer(&mut *writer, &properties).unwrap();
+    writer.push(b'}');
+}
+
+/// Reads one polygon's rings from GeoJSON coordinates, closing any left open.
+fn rings(coordinates: &Value) -> Result<geo::Area, String> {
+    let position = |position: &Value| match (position[0].as_f64(), position[1].as_f64()) {
+        (Some(lon), Some(lat)) => Ok((lat as f32, lon as f32)),
+        _ => Err(format!("invalid position {position}")),
+    };
+
+    let rings = coordinates.as_array().ok_or("a Polygon's coordinates must be an array")?;
+    let mut area = Vec::with_capacity(rings.len());
+    for ring in rings {
+        let mut ring = ring
+            .as_array()
+            .ok_or("a ring must be an array of positions")?
+            .iter()
+            .map(position)
+            .collect::<Result<Vec<_>, _>>()?;
+        if ring.first() != ring.last() {
+            ring.push(ring[0]);
+        }
+        if ring.len() < 4 {
+            return Err("a ring needs at least three positions".to_string());
+        }
+        area.push(ring);
+    }
+
+    match area.is_empty() {
+        true => Err("a Polygon needs an outline".to_string()),
+        false => Ok(area),
+    }
+}
+
+/// Collects the polygons of a GeoJSON object, looking inside features and collections.
+fn collect_areas(value: &Value, areas: &mut Vec<geo::Area>) -> Result<(), String> {
+    let list = |name: &str| {
+        value[name]
+            .as_array()
+            .ok_or_else(|| format!("{name} must be an array"))
+    };
+
+    match value["type"].as_str() {
+        Some("FeatureCollection") => {
+            for feature in list("features")? {
+                collect_areas(feature, areas)?;
+            }
+        }
+        Some("GeometryCollection") => {
+            for geometry in list("geometries")? {
+                collect_areas(geometry, areas)?;
+            }
+        }
+        Some("Feature") => collect_areas(&value["geometry"], areas)?,
+        Some("Polygon") => areas.push(rings(&value["coordinates"])?),
+        Some("MultiPolygon") => {
+            for polygon in list("coordinates")? {
+                areas.push(rings(polygon)?);
+            }
+        }
+        Some(kind) => return Err(format!("{kind} has no area, expected a Polygon")),
+        None => return Err("missing type".to_string()),
+    }
+
+    Ok(())
+}
+
+/// Reads the polygons of a GeoJSON geometry, feature or feature collection, such as a county or
+/// CWA outline to keep the warnings within.
+pub fn read_areas(json: &str) -> Result<Vec<geo::Area>, String> {
+    let value: Value = serde_json::from_str(json).map_err(|e| e.to_string())?;
+    let mut areas = Vec::new();
+    collect_areas(&value, &mut areas)?;
+
+    match areas.is_empty() {
+        true => Err("no polygons".to_string()),
+        false => Ok(areas),
+    }
+}
@@ -0,0 +1,145 @@
+use crate::fetch;
+use crate::product::Vtec;
+use chrono::NaiveDate;
+use lazy_static::lazy_static;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+
+lazy_static! {
+    /// The days each VTEC code was seen on, such as `O.NEW.KOUN.TO.W.0045`.
+    static ref INDEX: RwLock<BTreeMap<String, BTreeSet<NaiveDate>>> = RwLock::new(BTreeMap::new());
+}
+
+/// Records the VTEC codes in one day's text, so they can be searched later.
+///
+/// Each line is parsed in isolation, so a garbled one that panics the parser is only left out.
+pub fn add(text: &str, date: NaiveDate) {
+    let codes: Vec<_> = text
+        .lines()
+        .filter(|line| line.starts_with('/'))
+        .filter_map(|line| {
+            std::panic::catch_unwind(|| Vtec::parse(line).map(|vtec| vtec.code()))
+                .ok()
+                .flatten()
+        })
+        .collect();
+
+    if codes.is_empty() {
+        return;
+    }
+
+    let mut index = INDEX.write().unwrap();
+    for code in codes {
+        index.entry(code).or_default().insert(date);
+    }
+}
+
+/// Finds the `TOR_YYYYMMDD.txt` files under a directory, with their days.
+fn find_days(dir: &Path, days: &mut Vec<(PathBuf, NaiveDate)>) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+
+    for path in entries.flatten().map(|entry| entry.path()) {
+        if path.is_dir() {
+            find_days(&path, days);
+            continue;
+        }
+
+        let date = path
+            .file_name()
+            .and_then(|name| name.to_str()?.strip_prefix("TOR_")?.strip_suffix(".txt"))
+            .and_then(|date| NaiveDate::parse_from_str(date, "%Y%m%d").ok());
+        if let Some(date) = date {
+            days.push((path, date));
+        }
+    }
+}
+
+/// Records the VTEC codes of every day in a local archive, returning how many days were read.
+fn add_archive(dir: &Path) -> usize {
+    let mut days = Vec::new();
+    find_days(dir, &mut days);
+
+    for (path, date) in &days {
+        match fetch::read_file(path) {
+            Ok(text) => add(&text, *date),
+            Err(e) => eprintln!("Failed to index {}: {e}", path.display()),
+        }
+    }
+
+    days.len()
+}
+
+/// Indexes a local archive in the background, so searches find events on days no request has read
+/// yet.
+pub fn load(dir: PathBuf) {
+    std::thread::spawn(move || {
+        let count = add_archive(&dir);
+        println!("Indexed {count} days from {}", dir.display());
+    });
+}
+
+/// Normalizes a full or partial VTEC code to search for, such as `/O.NEW.KOUN.TO.W.0045` or
+/// `KOUN.TO.W.0045`.
+pub fn normalize(code: &str) -> Option<String> {
+    let code = code.trim().trim_matches('/').to_ascii_uppercase();
+    let valid = !code.is_empty()
+        && code
+            .split('.')
+            .all(|part| !part.is_empty() && part.chars().all(|c| c.is_ascii_alphanumeric()));
+
+    valid.then_some(code)
+}
+
+/// Tests if a VTEC code contains a normalized search, on whole components.
+pub fn matches(code: &str, search: &str) -> bool {
+    format!(".{code}.").contains(&format!(".{search}."))
+}
+
+/// Finds the days seen with products of one tornado warning event, between two days.
+///
+/// The office can be given with or without its leading letter, such as `OUN` or `KOUN`.
+pub fn event_days(office: &str, etn: u32, from: NaiveDate, to: NaiveDate) -> BTreeSet<NaiveDate> {
+    let is_event = |code: &str| {
+        let parts: Vec<_> = code.split('.').collect();
+        matches!(parts[..], [_, _, issuer, "TO", "W", number]
+            if issuer.ends_with(office) && issuer.len() <= office.len() + 1
+                && number.parse() == Ok(etn))
+    };
+
+    INDEX
+        .read()
+        .unwrap()
+        .iter()
+        .filter(|(code, _)| is_event(code))
+        .flat_map(|(_, dates)| dates.range(from..=to).copied())
+        .collect()
+}
+
+/// Finds the days with products matching a normalized search.
+pub fn search(search: &str) -> BTreeSet<NaiveDate> {
+    INDEX
+        .read()
+        .unwrap()
+        .iter()
+        .filter(|(code, _)| matches(code, search))
+        .flat_map(|(_, dates)| dates.iter().copied())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn archives() {
+        let fixtures = Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures"));
+        assert_eq!(add_archive(fixtures), 1);
+
+        let day = NaiveDate::from_ymd_opt(2024, 5, 20).unwrap();
+        assert!(search("KOUN.TO.W.0020").contains(&day));
+        assert!(event_days("OUN", 20, day, day).contains(&day));
+    }
+}
@@ -0,0 +1,74 @@
+use crate::HttpResult;
+use lazy_static::lazy_static;
+use std::collections::HashMap;
+use std::panic::AssertUnwindSafe;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// The state of a background job.
+#[derive(Clone)]
+pub enum Status {
+    Running,
+    /// The generated body and its content type.
+    Done(Vec<u8>, &'static str),
+    Failed,
+}
+
+struct Job {
+    status: Status,
+    finished: Option<Instant>,
+}
+
+lazy_static! {
+    static ref JOBS: Mutex<HashMap<String, Job>> = Mutex::new(HashMap::new());
+}
+
+/// Removes jobs that finished longer than `ttl` ago.
+fn purge(jobs: &mut HashMap<String, Job>, ttl: Duration) {
+    jobs.retain(|_, job| job.finished.is_none_or(|t| t.elapsed() < ttl));
+}
+
+/// Runs a job on a background thread, keeping its result for `ttl` after it finishes.
+pub fn spawn<F>(id: String, ttl: Duration, content_type: &'static str, f: F)
+where
+    F: FnOnce() -> HttpResult<Vec<u8>> + Send + 'static,
+{
+    {
+        let mut jobs = JOBS.lock().unwrap();
+        purge(&mut jobs, ttl);
+        jobs.insert(
+            id.clone(),
+            Job {
+                status: Status::Running,
+                finished: None,
+            },
+        );
+    }
+
+    std::thread::spawn(move || {
+        // A job that panics still has to finish, or it would be polled as running forever.
+        let status = match std::panic::catch_unwind(AssertUnwindSafe(f)) {
+            Ok(Ok(bytes)) => Status::Done(bytes, content_type),
+            Ok(Err(e)) => {
+                eprintln!("[{id}] Job failed: {e}");
+                Status::Failed
+            }
+            Err(_) => {
+                eprintln!("[{id}] Job panicked");
+                Status::Failed
+            }
+        };
+
+        if let Some(job) = JOBS.lock().unwrap().get_mut(&id) {
+            job.status = status;
+            job.finished = Some(Instant::now());
+        }
+    });
+}
+
+/// Returns the status of a job, if it exists.
+pub fn status(id: &str, ttl: Duration) -> Option<Status> {
+    let mut jobs = JOBS.lock().unwrap();
+    purge(&mut jobs, ttl);
+    jobs.get(id).map(|job| job.status.clone())
+}
@@ -0,0 +1,293 @@
+use crate::event::{Event, Grouper};
+use crate::query::{Query, DEFAULT_TIMEFMT};
+use crate::warning::{self, Warning};
+use crate::{config, fetch, index, render, HttpResult};
+use chrono::{NaiveTime, Utc};
+use lazy_static::lazy_static;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashSet;
+use std::hash::{Hash, Hasher};
+use std::io::{Read, Write};
+use std::panic::AssertUnwindSafe;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+use tiny_http::{Header, Request, Response};
+use tungstenite::{handshake::derive_accept_key, protocol::Role, Message, WebSocket};
+
+/// A change to a warning, announced to live subscribers.
+pub struct Update {
+    /// Either `new`, `updated` or `cancelled`.
+    pub event: &'static str,
+    pub warning: Warning,
+    /// The warning as JSON.
+    pub json: String,
+}
+
+impl Update {
+    fn new(warning: Warning) -> Self {
+        let event = match warning.vtec.as_ref().map(|vtec| vtec.action.as_str()) {
+            None | Some("NEW") => "new",
+            Some("CAN" | "EXP" | "UPG") => "cancelled",
+            Some(_) => "updated",
+        };
+
+        Self {
+            event,
+            json: serde_json::to_string(&warning).unwrap(),
+            warning,
+        }
+    }
+}
+
+lazy_static! {
+    /// Everyone waiting on warning updates.
+    static ref SUBSCRIBERS: Mutex<Vec<Sender<Arc<Update>>>> = Mutex::new(Vec::new());
+    /// The live placefile last rendered, and when.
+    static ref RENDERED: Mutex<Option<(Vec<u8>, Instant)>> = Mutex::new(None);
+}
+
+/// Subscribes to warning updates as they arrive.
+pub fn subscribe() -> Receiver<Arc<Update>> {
+    let (sender, receiver) = mpsc::channel();
+    SUBSCRIBERS.lock().unwrap().push(sender);
+    receiver
+}
+
+/// Sends a warning update to every subscriber, dropping those that have gone away.
+fn broadcast(warning: Warning) {
+    let update = Arc::new(Update::new(warning));
+    println!(
+        "[live] Announcing {} warning issued {}",
+        update.event, update.warning.issued
+    );
+    SUBSCRIBERS
+        .lock()
+        .unwrap()
+        .retain(|sender| sender.send(update.clone()).is_ok());
+}
+
+/// Identifies a product segment, so it is only announced once.
+fn key(text: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    text.trim().hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Fetches yesterday's and today's warnings and announces the ones not seen before.
+async fn poll(client: &fetch::HttpsClient, seen: &mut HashSet<u64>, announce: bool) {
+    let config = config::get();
+    let today = Utc::now().date_naive();
+    let mut current = HashSet::new();
+
+    for date in [today.pred_opt().unwrap(), today] {
+        let text = match fetch::fetch_day(client, &config, date, "live").await {
+            Ok(text) => text,
+            Err(e) => {
+                eprintln!("[live] Failed to poll {date}: {e}");
+                return;
+            }
+        };
+
+        index::add(&text, date);
+
+        for segment in text.split("$$").filter(|text| warning::is_valid(text)) {
+            let key = key(segment);
+            current.insert(key);
+
+            if !announce || seen.contains(&key) {
+                continue;
+            }
+
+            // Isolated like any other read, so one garbled product can't stop live mode.
+            match crate::parse_isolated(segment, &Query::default(), "live") {
+                Ok(Some(warning)) => broadcast(warning),
+                Ok(None) => (),
+                Err(skip) => eprintln!("[live] Skipping warning: {skip}"),
+            }
+        }
+    }
+
+    *seen = current;
+}
+
+/// Polls the upstreams for new warnings in the background while live mode is enabled.
+///
+/// Warnings that already exist when polling starts are not announced.
+pub fn start() {
+    std::thread::spawn(|| {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let client = fetch::client(&config::get());
+        let mut seen = None;
+
+        loop {
+            let config = config::get();
+            if config.live.enabled {
+                let announce = seen.is_some();
+                let seen = seen.get_or_insert_with(HashSet::new);
+                let polled = std::panic::catch_unwind(AssertUnwindSafe(|| {
+                    runtime.block_on(poll(&client, seen, announce))
+                }));
+                if polled.is_err() {
+                    eprintln!("[live] Polling panicked, trying again next interval");
+                }
+            } else {
+                seen = None;
+            }
+
+            std::thread::sleep(Duration::from_secs(config.live.interval));
+        }
+    });
+}
+
+/// Returns the live placefile, rendering it at most once per poll interval.
+///
+/// Every radar client showing it refreshes it, so the others wait on the one rendering it and share
+/// the result instead of each reading the upstreams.
+pub fn placefile(config: &config::Config, id: &str) -> HttpResult<Vec<u8>> {
+    let mut rendered = RENDERED.lock().unwrap_or_else(PoisonError::into_inner);
+    if let Some((body, at)) = rendered.as_ref() {
+        if at.elapsed() < Duration::from_secs(config.live.interval) {
+            return Ok(body.clone());
+        }
+    }
+
+    let body = render(config, id)?;
+    *rendered = Some((body.clone(), Instant::now()));
+    Ok(body)
+}
+
+/// Drops the rendered live placefile, such as when the config changes.
+pub fn clear() {
+    *RENDERED.lock().unwrap_or_else(PoisonError::into_inner) = None;
+}
+
+/// Renders the latest polygon of every warning in effect, as a placefile.
+///
+/// Warnings that expired within the last `expired_grace` minutes are kept, but dimmed and labeled
+/// as expired.
+fn render(config: &config::Config, id: &str) -> HttpResult<Vec<u8>> {
+    let now = Utc::now();
+    let today = now.date_naive().and_time(NaiveTime::MIN).and_utc();
+    let grace = chrono::Duration::minutes(config.live.expired_grace);
+
+    let mut writer = render::live_header(config);
+    let mut events = Grouper::new(|event: Event| {
+        let (warning, timefmt) = (event.latest(), DEFAULT_TIMEFMT);
+        match event.ended() {
+            Some(ended) if ended > now => render::warning(&mut writer, warning, config, timefmt),
+            Some(ended) if ended > now - grace => {
+                render::expired(&mut writer, warning, config, timefmt)
+            }
+            _ => (),
+        }
+    });
+
+    let query = Query::range(today - chrono::Duration::days(1), today);
+    crate::find_warnings(&query, config, id, |warning| events.push(warning))?;
+    events.finish();
+
+    Ok(writer)
+}
+
+/// How often websockets are pinged, and event streams sent a keep-alive.
+const PING_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How long a read from a client can block before the client is dropped, which is long enough for
+/// a websocket to answer a ping.
+pub const READ_TIMEOUT: Duration = Duration::from_secs(2 * PING_INTERVAL.as_secs());
+
+/// Returns the handshake response key if the request is a websocket upgrade.
+pub fn accept_key(request: &Request) -> Option<String> {
+    let header = |name: &'static str| {
+        request
+            .headers()
+            .iter()
+            .find(|h| h.field.equiv(name))
+            .map(|h| h.value.as_str())
+    };
+
+    header("Upgrade").filter(|v| v.eq_ignore_ascii_case("websocket"))?;
+    header("Sec-WebSocket-Key").map(|key| derive_accept_key(key.as_bytes()))
+}
+
+/// Reads what a websocket client sent until it answers a ping, replying to its own pings along
+/// the way. Returns whether the socket is still open, after answering a close frame if it sent
+/// one.
+///
+/// A client that vanished without closing its connection is given up on after `READ_TIMEOUT`.
+fn read_until_pong<S: Read + Write>(socket: &mut WebSocket<S>) -> bool {
+    loop {
+        match socket.read() {
+            Ok(Message::Pong(_)) => return true,
+            Ok(Message::Close(_)) => {
+                // Reading the close frame queues the reply, which goes out on the flush.
+                let _ = socket.flush();
+                return false;
+            }
+            Ok(_) => continue,
+            Err(_) => return false,
+        }
+    }
+}
+
+/// Upgrades a request to a websocket and pushes each newly issued warning to it as JSON.
+///
+/// The upgraded connection can't be read and written at once, so what the client sent is read
+/// after each ping, up to its pong. Clients answer pings on their own, so this only waits for a
+/// round trip, and a close frame is answered within one ping interval.
+pub fn serve_websocket(request: Request, accept: String, id: &str) {
+    let response = Response::empty(101)
+        .with_header(Header::from_bytes("Sec-WebSocket-Accept", accept).unwrap());
+    let stream = request.upgrade("websocket", response);
+    let mut socket = WebSocket::from_raw_socket(stream, Role::Server, None);
+    let updates = subscribe();
+
+    loop {
+        // Ping every so often so dead connections get noticed, and read what came in meanwhile.
+        let open = match updates.recv_timeout(PING_INTERVAL) {
+            Ok(update) if update.event == "new" => {
+                socket.send(Message::text(update.json.as_str())).is_ok()
+            }
+            Ok(_) => continue,
+            Err(RecvTimeoutError::Timeout) => {
+                socket.send(Message::Ping(Default::default())).is_ok()
+                    && read_until_pong(&mut socket)
+            }
+            Err(RecvTimeoutError::Disconnected) => {
+                let _ = socket.close(None).and_then(|_| socket.flush());
+                false
+            }
+        };
+
+        if !open {
+            break;
+        }
+    }
+
+    println!("[{id}] Websocket closed");
+}
+
+/// Streams every warning update to the request as server-sent events.
+pub fn serve_events(request: Request, id: &str) {
+    let mut writer = request.into_writer();
+    let updates = subscribe();
+
+    let mut result = write!(
+        writer,
+        "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\n\
+         Connection: close\r\nX-Request-Id: {id}\r\n\r\n"
+    );
+
+    while result.is_ok() {
+        // Send a comment every so often so dead connections get noticed.
+        result = match updates.recv_timeout(PING_INTERVAL) {
+            Ok(update) => write!(writer, "event: {}\ndata: {}\n\n", update.event, update.json),
+            Err(RecvTimeoutError::Timeout) => writer.write_all(b": keep-alive\n\n"),
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+        .and_then(|_| writer.flush());
+    }
+
+    println!("[{id}] Event stream closed");
+}
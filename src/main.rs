@@ -1,20 +1,82 @@
-use chrono::{DateTime, Datelike, Utc};
-use lazy_static::lazy_static;
+mod archive;
+mod cache;
+mod config;
+mod daemon;
+mod event;
+mod fetch;
+mod geo;
+mod geojson;
+mod index;
+mod jobs;
+mod live;
+mod metrics;
+mod mvt;
+mod openapi;
+mod output;
+mod product;
+mod publish;
+mod query;
+mod render;
+mod schedule;
+mod verify;
+mod warning;
+mod webhook;
+
+use chrono::{DateTime, FixedOffset, NaiveDate, Utc};
+use clap::{Parser, Subcommand};
+use event::{Event, EventKey, Grouper};
+use output::Output;
+use query::{Format, Mode, PerEvent, Query, Range};
 use regex::Regex;
-use std::collections::BTreeMap;
-use std::io::{Cursor, Write};
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::io::Cursor;
+use std::panic::AssertUnwindSafe;
+use std::path::PathBuf;
 use std::str::FromStr;
-use tiny_http::{Request, Response, Server, StatusCode};
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tiny_http::{Header, Method, Request, Response, Server, StatusCode};
+use warning::{Skip, Skips, Warning};
 
 /// An http error that will be returned as a response.
 #[derive(Debug)]
 enum HttpError {
     NotFound,
     BadRequest,
+    /// A query parameter that is missing, unknown or malformed, and why.
+    Invalid(String),
     ParseError(<hyper::Uri as FromStr>::Err),
     GetError(hyper::Error),
+    StatusError(hyper::StatusCode),
+    Timeout,
     ToBytesError(hyper::Error),
-    Utf8Error(std::string::FromUtf8Error),
+    BuildError(hyper::http::Error),
+    ReadError(std::io::Error),
+    WriteError(std::io::Error),
+    ReportError(String),
+    TaskError(tokio::task::JoinError),
+}
+
+impl fmt::Display for HttpError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HttpError::NotFound => write!(f, "not found"),
+            HttpError::BadRequest => write!(f, "bad request"),
+            HttpError::Invalid(e) => write!(f, "bad request: {e}"),
+            HttpError::ParseError(e) => write!(f, "invalid upstream url: {e}"),
+            HttpError::GetError(e) => write!(f, "upstream request failed: {e}"),
+            HttpError::StatusError(s) => write!(f, "upstream responded with {s}"),
+            HttpError::Timeout => write!(f, "upstream timed out"),
+            HttpError::ToBytesError(e) => write!(f, "failed to read upstream body: {e}"),
+            HttpError::BuildError(e) => write!(f, "failed to build request: {e}"),
+            HttpError::ReadError(e) => write!(f, "failed to read local archive: {e}"),
+            HttpError::WriteError(e) => write!(f, "failed to write file: {e}"),
+            HttpError::ReportError(e) => write!(f, "failed to parse storm reports: {e}"),
+            HttpError::TaskError(e) => write!(f, "background task failed: {e}"),
+        }
+    }
 }
 
 type HttpResult<T> = Result<T, HttpError>;
@@ -30,57 +92,201 @@ impl<T, E> TupIntoResult<T, E> for (Result<T, E>, Result<T, E>) {
     }
 }
 
+/// Decodes a percent-encoded query value.
+fn decode(value: &str) -> String {
+    percent_encoding::percent_decode_str(&value.replace('+', " "))
+        .decode_utf8_lossy()
+        .into_owned()
+}
+
 /// Parse queries for a link.
+///
+/// Every pair needs a name and a value, so typos are reported instead of silently dropped.
 fn parse_params(string: &str) -> HttpResult<BTreeMap<String, String>> {
-    lazy_static! {
-        static ref URL_PARSE: Regex = Regex::new(r"(?:\?|&)([^&=]+)=([^&=]+)").unwrap();
-    }
-
-    let captures: Vec<_> = URL_PARSE
-        .captures_iter(string)
-        .map(|cap| {
-            (
-                cap.get(1)
-                    .ok_or(HttpError::BadRequest)
-                    .map(|c| c.as_str().to_string()),
-                cap.get(2)
-                    .ok_or(HttpError::BadRequest)
-                    .map(|c| c.as_str().to_string()),
-            )
-                .into_result()
-        })
-        .collect::<Result<_, _>>()?;
+    use std::collections::btree_map::Entry;
 
-    Ok(BTreeMap::from_iter(captures.into_iter()))
+    let query = string.split_once('?').map_or("", |(_, query)| query);
+    let mut params = BTreeMap::new();
+
+    for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+        let (name, value) = match pair.split_once('=') {
+            Some((name, value)) if !name.is_empty() && !value.is_empty() => {
+                (decode(name), decode(value))
+            }
+            _ => {
+                return Err(HttpError::Invalid(format!(
+                    "expected name=value, got {}",
+                    decode(pair)
+                )))
+            }
+        };
+
+        // Repeatable parameters are joined with commas, and repeating any other is a mistake.
+        match params.entry(name) {
+            Entry::Vacant(entry) => {
+                entry.insert(value);
+            }
+            Entry::Occupied(mut entry) if query::is_repeatable(entry.key()) => {
+                let values: &mut String = entry.get_mut();
+                values.push(',');
+                values.push_str(&value);
+            }
+            Entry::Occupied(entry) => {
+                return Err(HttpError::Invalid(format!("repeated parameter {}", entry.key())))
+            }
+        }
+    }
+
+    Ok(params)
 }
 
-/// Shorthand for conting a timezone-less string and format to a datetime.
-fn to_utc(s: &str, fmt: &str) -> HttpResult<DateTime<Utc>> {
-    use chrono::naive::{NaiveDate, NaiveDateTime};
+/// Parses an ISO-8601 datetime such as `2011-04-27T18:00Z`, or a Unix timestamp in seconds.
+///
+/// Datetimes without an offset are taken as UTC. An unencoded `+` in an offset arrives as a space,
+/// so a space is read as one.
+fn to_utc(s: &str) -> HttpResult<DateTime<Utc>> {
+    use chrono::naive::NaiveDateTime;
+
+    if !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit()) {
+        return s
+            .parse()
+            .ok()
+            .and_then(|seconds| DateTime::from_timestamp(seconds, 0))
+            .ok_or(HttpError::BadRequest);
+    }
 
-    let naive_time = match NaiveDateTime::parse_from_str(s, fmt) {
-        Ok(time) => time,
-        Err(_) => NaiveDate::parse_from_str(s, fmt).map_err(|_| HttpError::BadRequest)?.and_hms(0, 0, 0),
+    let s = s.replace(' ', "+");
+    if let Ok(time) = DateTime::parse_from_rfc3339(&s)
+        .or_else(|_| DateTime::parse_from_str(&s, "%FT%H:%M%:z"))
+        .or_else(|_| DateTime::parse_from_str(&s, "%FT%H:%M%z"))
+    {
+        return Ok(time.with_timezone(&Utc));
+    }
+
+    let naive = s.strip_suffix(['Z', 'z']).unwrap_or(&s);
+    ["%FT%H:%M:%S%.f", "%FT%H:%M"]
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(naive, format).ok())
+        .map(|time| time.and_utc())
+        .ok_or(HttpError::BadRequest)
+}
+
+/// Parses a `tz` parameter, a UTC offset such as `-05:00`, `-5` or `-0500`, or a US time zone
+/// abbreviation such as `CDT`.
+fn parse_offset(s: &str) -> HttpResult<FixedOffset> {
+    let hours = match s.to_ascii_uppercase().as_str() {
+        "UTC" | "Z" => Some(0),
+        "EDT" => Some(-4),
+        "EST" | "CDT" => Some(-5),
+        "CST" | "MDT" => Some(-6),
+        "MST" | "PDT" => Some(-7),
+        "PST" => Some(-8),
+        _ => None,
+    };
+    if let Some(hours) = hours {
+        return Ok(FixedOffset::east_opt(hours * 3600).unwrap());
+    }
+
+    // An unencoded `+` arrives as a space.
+    let s = s.replace(' ', "+");
+    let (sign, rest) = match s.strip_prefix('-') {
+        Some(rest) => (-1, rest),
+        None => (1, s.strip_prefix('+').unwrap_or(&s)),
+    };
+    let (hours, minutes) = match rest.split_once(':') {
+        Some((hours, minutes)) => (hours, minutes),
+        None if rest.len() == 4 => rest.split_at(2),
+        None => (rest, "0"),
     };
 
-    Ok(DateTime::from_utc(
-        naive_time,
-        Utc,
-    ))
+    match (hours.parse::<i32>(), minutes.parse::<i32>()) {
+        (Ok(hours), Ok(minutes)) if hours <= 14 && minutes < 60 => {
+            FixedOffset::east_opt(sign * (hours * 3600 + minutes * 60)).ok_or(HttpError::BadRequest)
+        }
+        _ => Err(HttpError::BadRequest),
+    }
 }
 
-/// Parses a url string and returns the start and end time data as an HttpResult.
-fn parse_times(string: &str) -> HttpResult<(DateTime<Utc>, DateTime<Utc>)> {
-    let params = parse_params(string)?;
-    let (start, end) = (
-        params.get("start").ok_or(HttpError::BadRequest)?,
-        params.get("end").ok_or(HttpError::BadRequest)?,
-    );
+/// Parses the `start` and `end` parameters, or the ranges given by `range` instead, in order of
+/// their starts.
+///
+/// Each time is a date, one of `today`, `yesterday` or `this_week` (the Monday starting it), an
+/// ISO-8601 datetime, or a Unix timestamp. Days are in UTC, or in the offset given by `tz`, in
+/// which case they begin and end within UTC days.
+fn parse_times(params: &BTreeMap<String, String>) -> HttpResult<Vec<Range>> {
+    use chrono::{Datelike, Days};
+
+    let offset = match params.get("tz") {
+        Some(tz) => parse_offset(tz)
+            .map_err(|_| HttpError::Invalid(format!("invalid tz: {tz}, expected -05:00 or CDT")))?,
+        None => FixedOffset::east_opt(0).unwrap(),
+    };
+    let today = Utc::now().with_timezone(&offset).date_naive();
+    let monday = today - Days::new(today.weekday().num_days_from_monday().into());
+
+    let time = |name, value: Option<&str>, is_end| {
+        let value = value.ok_or_else(|| HttpError::Invalid(format!("missing {name}")))?;
+        let day = match value {
+            "today" => Some(today),
+            "yesterday" => today.pred_opt(),
+            "this_week" => Some(monday),
+            value => NaiveDate::parse_from_str(value, "%F").ok(),
+        };
+
+        match day {
+            Some(day) if offset.local_minus_utc() == 0 => {
+                Ok((day.and_hms_opt(0, 0, 0).unwrap().and_utc(), None))
+            }
+            Some(day) => {
+                // The day's first or last second in the offset.
+                let local = match is_end {
+                    true => day.and_hms_opt(23, 59, 59).unwrap(),
+                    false => day.and_hms_opt(0, 0, 0).unwrap(),
+                };
+                let time = (local - offset).and_utc();
+                Ok((time, Some(time)))
+            }
+            None => match to_utc(value) {
+                Ok(time) => Ok((time, Some(time))),
+                Err(_) => {
+                    let expected = "YYYY-MM-DD, today, YYYY-MM-DDTHH:MMZ or a Unix timestamp";
+                    Err(HttpError::Invalid(format!("invalid {name}: {value}, expected {expected}")))
+                }
+            },
+        }
+    };
 
-    (
-        to_utc(start, "%F").map_err(|_| HttpError::BadRequest),
-        to_utc(end, "%F").map_err(|_| HttpError::BadRequest),
-    ).into_result()
+    let range = |start: HttpResult<_>, end: HttpResult<_>| {
+        let ((start, after), (end, before)) = (start, end).into_result()?;
+        Ok(Range {
+            start,
+            end,
+            after,
+            before,
+        })
+    };
+
+    let (start, end) = (params.get("start"), params.get("end"));
+    let Some(ranges) = params.get("range") else {
+        let start = time("start", start.map(String::as_str), false);
+        return Ok(vec![range(start, time("end", end.map(String::as_str), true))?]);
+    };
+    if start.is_some() || end.is_some() {
+        return Err(HttpError::Invalid("range can't be used with start or end".to_string()));
+    }
+
+    let mut ranges = ranges
+        .split(',')
+        .map(|value| match value.split_once('/') {
+            Some((start, end)) => {
+                range(time("range", Some(start), false), time("range", Some(end), true))
+            }
+            None => Err(HttpError::Invalid(format!("invalid range: {value}, expected START/END"))),
+        })
+        .collect::<HttpResult<Vec<_>>>()?;
+    ranges.sort_by_key(|range| range.start);
+
+    Ok(ranges)
 }
 
 /// A macro to either return a static or bytes html response.
@@ -101,147 +307,930 @@ macro_rules! response {
     }};
 }
 
-/// Detects the severity of a warning text, and returns a color string and line width.
-fn warning_color(text: &str) -> (&str, f32) {
-    if text.contains("EMERGENCY") {
-        ("0 0 0", 5.)
-    } else if text.contains("PARTICULARLY DANGEROUS SITUATION") {
-        ("255 0 255", 4.)
-    } else if text.contains("OBSERVED") || text.contains("reported") {
-        ("150 0 0", 3.5)
-    } else {
-        ("255 0 0", 3.)
+/// The most websockets and event streams open at once, since each holds a thread while idle.
+const MAX_STREAMS: usize = 256;
+
+/// The number of websockets and event streams open.
+static STREAMS: AtomicUsize = AtomicUsize::new(0);
+
+/// Serves a websocket or event stream on a thread of its own, so it doesn't hold a worker for as
+/// long as it is open. Refuses it if `MAX_STREAMS` are already open.
+fn spawn_stream(request: Request, id: String, serve: impl FnOnce(Request, &str) + Send + 'static) {
+    if STREAMS.fetch_add(1, Ordering::Relaxed) >= MAX_STREAMS {
+        STREAMS.fetch_sub(1, Ordering::Relaxed);
+        eprintln!("[{id}] Refusing stream, {MAX_STREAMS} are already open");
+        let _ = request.respond(response!(503, Cursor::new(b"Too many streams open\n".to_vec())));
+        return;
+    }
+
+    std::thread::spawn(move || {
+        let _ = std::panic::catch_unwind(AssertUnwindSafe(|| serve(request, &id)));
+        STREAMS.fetch_sub(1, Ordering::Relaxed);
+    });
+}
+
+/// Parses one product segment, returning `None` if it doesn't match the query.
+fn parse_segment(segment: &str, query: &Query, id: &str) -> Result<Option<Warning>, Skip> {
+    let mut warning = match Warning::parse(segment) {
+        Ok(warning) => warning,
+        // Stray bytes were replaced while decoding, and are the likely reason.
+        Err(_) if segment.contains(char::REPLACEMENT_CHARACTER) => return Err(Skip::InvalidUtf8),
+        Err(skip) => return Err(skip),
+    };
+    if let Err(reason) = geo::validate(&mut warning.polygon) {
+        eprintln!("[{id}] Skipping warning issued {}: {reason}", warning.issued);
+        return Err(Skip::InvalidPolygon);
+    }
+    if let Some(tolerance) = query.simplify {
+        warning.polygon = geo::simplify(&warning.polygon, tolerance);
     }
+
+    Ok(Some(warning).filter(|warning| query.matches(warning)))
 }
 
-/// Tests if a warning is valid.
-fn is_valid(text: &str) -> bool {
-    !(text.contains("TEST") || text.len() < 50 || text.contains("404"))
+/// Parses one product segment like `parse_segment`, in isolation, so a product that panics the
+/// parser is skipped instead of taking its caller down with it.
+fn parse_isolated(segment: &str, query: &Query, id: &str) -> Result<Option<Warning>, Skip> {
+    std::panic::catch_unwind(AssertUnwindSafe(|| parse_segment(segment, query, id)))
+        .unwrap_or_else(|_| {
+            eprintln!("[{id}] Skipping a product that panicked while parsing");
+            Err(Skip::Panic)
+        })
 }
 
-/// Finds all warnings in a given range.
-fn find_warnings((mut start, end): (DateTime<Utc>, DateTime<Utc>)) -> HttpResult<Vec<u8>> {
-    use futures::{stream, StreamExt, TryStreamExt};
-    use hyper::{body, client::Client};
+/// Parses the warnings in one day's text that match a query.
+///
+/// The text is only borrowed, and owned warnings are only created for products that pass the
+/// text filters. Products that can't be used are skipped, and counted by why. Each product is
+/// parsed in isolation, so even one that panics only loses itself.
+fn parse_day(text: &str, query: &Query, id: &str) -> (Vec<Warning>, Skips) {
+    let mut warnings = Vec::new();
+    let mut skips = Skips::new();
+    let segments = text
+        .split("$$")
+        .filter(|segment| warning::is_valid(segment) && query.matches_text(segment));
 
-    lazy_static! {
-        static ref PATH: Regex = Regex::new(r"LAT\.\.\.LON [\d{4}\s]+").unwrap();
-        static ref TIME: Regex = Regex::new(r".(\d{6}T\d{4}Z)-").unwrap();
+    for segment in segments {
+        match parse_isolated(segment, query, id) {
+            Ok(Some(warning)) => warnings.push(warning),
+            Ok(None) => (),
+            Err(skip) => *skips.entry(skip).or_default() += 1,
+        }
     }
 
-    let mut hours = Vec::new();
+    metrics::record(warnings.len(), &skips);
+    (warnings, skips)
+}
 
-    while start <= end {
-        let url = format!("https://mesonet.agron.iastate.edu/archive/data/{y}/{m:0>2}/{d:0>2}/text/noaaport/TOR_{y}{m:0>2}{d:0>2}.txt",
-            y=start.year(), m=start.month(), d=start.day());
-        hours.push(url);
-        start = start + chrono::Duration::days(1);
-    }
+/// Downloads and parses the warnings matching a query, handing each one to `each`.
+///
+/// Days are handed over in order as they arrive, so only a few days are held in memory at once no
+/// matter how long the range is. Each day is parsed on the blocking thread pool, so parsing uses
+/// every core while later days download. Days in more than one of the query's ranges are read once.
+fn find_warnings(
+    query: &Query,
+    config: &config::Config,
+    id: &str,
+    each: impl FnMut(Warning),
+) -> HttpResult<Skips> {
+    find_warnings_on(query.dates(), query, config, id, each)
+}
+
+/// Downloads and parses the warnings matching a query on the given days, like `find_warnings`.
+///
+/// Every day read is also added to the VTEC index. Returns how many products were skipped.
+fn find_warnings_on(
+    days: Vec<NaiveDate>,
+    query: &Query,
+    config: &config::Config,
+    id: &str,
+    mut each: impl FnMut(Warning),
+) -> HttpResult<Skips> {
+    use futures::{stream, StreamExt, TryStreamExt};
 
-    println!("Reading {} files...", hours.len());
+    // Downloads wait for the download slots shared by every request, so queueing as many as
+    // there are slots keeps this request's share of them busy.
+    let concurrency = config.concurrency;
+    println!("[{id}] Reading {} files...", days.len());
 
-    let https = hyper_tls::HttpsConnector::new();
-    let client = &Client::builder().build::<_, hyper::Body>(https);
-    let reqs = stream::iter(hours)
-        .map(|url| async move {
-            client
-                .get(url.parse().map_err(HttpError::ParseError)?)
-                .await
-                .map_err(HttpError::GetError)
-        })
-        .buffer_unordered(8)
-        .and_then(|res| async {
-            String::from_utf8(
-                body::to_bytes(res)
+    let client = &fetch::client(config);
+    let shared_query = Arc::new(query.clone());
+    let reports = verify::storm_events(config);
+    // A local archive works offline, so only the Storm Events files verify warnings read from it.
+    let use_lsrs = query.verified && config.local_archive.is_none();
+    if query.verified && !use_lsrs && reports.is_none() {
+        let reason = "verified=1 needs storm_events when reading a local archive";
+        return Err(HttpError::Invalid(reason.to_string()));
+    }
+    let mut parsed_days = stream::iter(days)
+        .map(|date| {
+            let query = shared_query.clone();
+            let reports = reports.clone();
+            async move {
+                let text = fetch::fetch_day(client, config, date, id).await?;
+                let lsrs = match use_lsrs {
+                    true => Some(fetch::fetch_lsrs(client, config, date).await?),
+                    false => None,
+                };
+                let id = id.to_string();
+                tokio::task::spawn_blocking(move || {
+                    index::add(&text, date);
+                    let (mut warnings, skips) = parse_day(&text, &query, &id);
+                    let sources: Vec<&[verify::Report]> = [reports.as_deref(), lsrs.as_ref()]
+                        .into_iter()
+                        .flatten()
+                        .map(Vec::as_slice)
+                        .collect();
+                    if !sources.is_empty() {
+                        for warning in &mut warnings {
+                            // A warning that can't be checked is left unverified, not the day.
+                            let verified = std::panic::catch_unwind(AssertUnwindSafe(|| {
+                                sources.iter().any(|reports| verify::verified(warning, reports))
+                            }));
+                            match verified {
+                                Ok(verified) => warning.verified = Some(verified),
+                                Err(_) => eprintln!("[{id}] Verifying a warning panicked"),
+                            }
+                        }
+                    }
+                    if query.verified {
+                        warnings.retain(|warning| warning.verified == Some(true));
+                    }
+                    Ok((warnings, skips))
+                })
                     .await
-                    .map_err(HttpError::ToBytesError)?
-                    .to_vec(),
-            )
-            .map_err(HttpError::Utf8Error)
+                    .map_err(HttpError::TaskError)?
+            }
         })
-        .try_collect::<Vec<String>>();
+        .buffered(concurrency);
 
-    let warnings: Vec<String> = tokio::runtime::Runtime::new()
-        .unwrap()
-        .block_on(reqs)?
-        .into_iter()
-        .flat_map(|text| text.split("$$").map(|s| s.to_owned()).collect::<Vec<_>>())
-        .filter(|text| is_valid(text))
+    let mut skips = Skips::new();
+    tokio::runtime::Runtime::new().unwrap().block_on(async {
+        while let Some((warnings, day_skips)) = parsed_days.try_next().await? {
+            warnings.into_iter().for_each(&mut each);
+            for (skip, count) in day_skips {
+                *skips.entry(skip).or_default() += count;
+            }
+        }
+
+        Ok::<_, HttpError>(())
+    })?;
+
+    if !skips.is_empty() {
+        let summary: Vec<_> = skips.iter().map(|(skip, count)| format!("{count} {skip}")).collect();
+        println!("[{id}] Skipped products: {}", summary.join(", "));
+    }
+    println!("[{id}] Done.");
+
+    Ok(skips)
+}
+
+/// Finds the warnings matching a query like `find_warnings`, keeping only the products of each
+/// event that the query asks for.
+fn select_warnings(
+    query: &Query,
+    config: &config::Config,
+    id: &str,
+    mut each: impl FnMut(Warning),
+) -> HttpResult<Skips> {
+    match query.per_event {
+        PerEvent::All => find_warnings(query, config, id, each),
+        PerEvent::First => {
+            let mut seen = HashSet::new();
+            find_warnings(query, config, id, |warning| {
+                let key = EventKey::among(&warning, |key| seen.contains(key));
+                if key.is_none_or(|key| seen.insert(key)) {
+                    each(warning);
+                }
+            })
+        }
+        PerEvent::Latest => {
+            let mut events = Grouper::new(|mut event: Event| each(event.warnings.pop().unwrap()));
+            let skips = find_warnings(query, config, id, |warning| events.push(warning))?;
+            events.finish();
+            Ok(skips)
+        }
+    }
+}
+
+/// Generates the response body for a query, in the format it asks for.
+///
+/// Warnings are rendered as soon as they are parsed, so only the output is kept in memory.
+fn generate(query: &Query, config: &config::Config, id: &str) -> HttpResult<Vec<u8>> {
+    if query.count {
+        return count(query, config, id);
+    }
+
+    let mut output = Output::new(query, config);
+
+    let skips = match query.mode {
+        Mode::Outline | Mode::Fill => {
+            select_warnings(query, config, id, |warning| output.warning(&warning))?
+        }
+        Mode::Track => {
+            let mut events = Grouper::new(|event| output.track(&event));
+            let skips = find_warnings(query, config, id, |warning| events.push(warning))?;
+            events.finish();
+            skips
+        }
+    };
+
+    if query.skips {
+        output.skips(&skips);
+    }
+
+    Ok(output.finish())
+}
+
+/// Counts the warnings matching a query without rendering them.
+fn count(query: &Query, config: &config::Config, id: &str) -> HttpResult<Vec<u8>> {
+    let mut count = 0;
+    select_warnings(query, config, id, |_| count += 1)?;
+
+    Ok(match query.format {
+        Format::GeoJson => serde_json::to_vec(&serde_json::json!({ "count": count })).unwrap(),
+        _ => format!("{count}\n").into_bytes(),
+    })
+}
+
+/// Generates a new id for a request or job.
+fn new_id() -> String {
+    static COUNTER: AtomicU32 = AtomicU32::new(0);
+
+    format!(
+        "{:x}-{:04x}",
+        Utc::now().timestamp_millis(),
+        COUNTER.fetch_add(1, Ordering::Relaxed) & 0xffff
+    )
+}
+
+/// Returns the client-supplied request id if it is sensible, otherwise generates a new one.
+fn request_id(request: &Request) -> String {
+    let supplied = request
+        .headers()
+        .iter()
+        .find(|h| h.field.equiv("X-Request-Id"))
+        .map(|h| h.value.as_str())
+        .filter(|v| {
+            !v.is_empty()
+                && v.len() <= 64
+                && v.chars().all(|c| c.is_ascii_alphanumeric() || "-_.:".contains(c))
+        });
+
+    supplied.map_or_else(new_id, str::to_string)
+}
+
+type HttpResponse = Response<Cursor<Vec<u8>>>;
+
+/// Responds with a generated body of the given content type.
+fn body_response(bytes: Vec<u8>, content_type: &str) -> HttpResponse {
+    let mut response = response!(200, Cursor::new(bytes))
+        .with_header(Header::from_bytes("Content-Type", content_type).unwrap());
+
+    if content_type == "application/zip" {
+        response.add_header(
+            Header::from_bytes("Content-Disposition", "attachment; filename=\"warnings.zip\"").unwrap(),
+        );
+    }
+
+    response
+}
+
+/// Builds a response body covering `days` days with `f`, handing large ranges off to a background
+/// job.
+fn run<F>(
+    days: i64,
+    content_type: &'static str,
+    config: Arc<config::Config>,
+    id: &str,
+    f: F,
+) -> HttpResult<HttpResponse>
+where
+    F: FnOnce(&config::Config, &str) -> HttpResult<Vec<u8>> + Send + 'static,
+{
+    if config.async_threshold_days == 0 || days <= config.async_threshold_days {
+        return f(&config, id).map(|bytes| body_response(bytes, content_type));
+    }
+
+    let job = new_id();
+    println!("[{id}] Generating {days} days in job {job}");
+    let ttl = Duration::from_secs(config.job_ttl);
+    let job_id = job.clone();
+    jobs::spawn(job.clone(), ttl, content_type, move || f(&config, &job_id));
+
+    let body = format!("Job {job} started. Poll /jobs/{job} for the result.\n");
+    Ok(response!(202, Cursor::new(body.into_bytes()))
+        .with_header(Header::from_bytes("Location", format!("/jobs/{job}")).unwrap()))
+}
+
+/// The largest request body read, which is enough for a detailed county or CWA outline.
+const MAX_BODY: u64 = 4 << 20;
+
+/// Reads a request's body as text, refusing ones over `MAX_BODY`.
+fn read_body(request: &mut Request) -> HttpResult<String> {
+    use std::io::Read;
+
+    let mut body = String::new();
+    request
+        .as_reader()
+        .take(MAX_BODY + 1)
+        .read_to_string(&mut body)
+        .map_err(|_| HttpError::Invalid("body isn't UTF-8 text".to_string()))?;
+
+    match body.len() as u64 > MAX_BODY {
+        true => Err(HttpError::Invalid(format!("body over {} MiB", MAX_BODY >> 20))),
+        false => Ok(body),
+    }
+}
+
+/// Serves `/warnings.txt`, keeping only the warnings intersecting the polygons of a GeoJSON body
+/// if one was POSTed.
+fn warnings(
+    url: &str,
+    body: Option<&str>,
+    config: Arc<config::Config>,
+    id: &str,
+) -> HttpResult<HttpResponse> {
+    use std::hash::{DefaultHasher, Hash, Hasher};
+
+    let mut query = Query::parse(url)?;
+    let days = query.days();
+    let content_type = query.content_type();
+
+    let mut params = query::resolve_times(parse_params(url)?, &query);
+    if let Some(body) = body {
+        let areas = geojson::read_areas(body)
+            .map_err(|e| HttpError::Invalid(format!("invalid GeoJSON body: {e}")))?;
+        query.areas = Some(areas);
+
+        // Bodies can be megabytes, so they are keyed by their hash.
+        let mut hasher = DefaultHasher::new();
+        body.hash(&mut hasher);
+        params.insert("body".to_string(), format!("{:016x}", hasher.finish()));
+    }
+    let key = cache::key("/warnings.txt", &params, query::PARAMS);
+    if let Some((body, content_type)) = cache::get(&key) {
+        println!("[{id}] Serving from cache");
+        return Ok(body_response(body.to_vec(), content_type));
+    }
+
+    let ttl = cache::ttl(&config, query.end());
+    run(days, content_type, config, id, move |config, id| {
+        let body = generate(&query, config, id)?;
+        cache::put(config, key, body.clone(), content_type, ttl);
+        Ok(body)
+    })
+}
+
+/// Serves `/tiles/{z}/{x}/{y}.mvt`, the warnings over one tile as a Mapbox Vector Tile.
+///
+/// Map clients can't follow a job, so tiles of ranges longer than `async_threshold_days` answer 503
+/// with a Retry-After while the range's warnings are found in the background.
+fn tile(
+    tile_path: &str,
+    url: &str,
+    config: Arc<config::Config>,
+    id: &str,
+) -> HttpResult<HttpResponse> {
+    const CONTENT_TYPE: &str = "application/vnd.mapbox-vector-tile";
+
+    let parts: Vec<_> = tile_path
+        .strip_suffix(".mvt")
+        .ok_or(HttpError::NotFound)?
+        .split('/')
         .collect();
+    let [z, x, y] = parts[..] else {
+        return Err(HttpError::NotFound);
+    };
+    let z: u8 = z.parse().map_err(|_| HttpError::NotFound)?;
+    let (x, y): (u32, u32) = match (x.parse(), y.parse()) {
+        (Ok(x), Ok(y)) if z <= mvt::MAX_ZOOM && x < 1 << z && y < 1 << z => (x, y),
+        _ => return Err(HttpError::NotFound),
+    };
 
-    let mut writer = Vec::new();
-    writeln!(&mut writer, "Title: Past TORs\nRefresh: 9999\n").unwrap();
-
-    for warning in warnings {
-        let mut path: Vec<f32> = PATH
-            .find(&warning)
-            .unwrap_or_else(|| panic!("No path found: {warning}"))
-            .as_str()
-            .split_whitespace()
-            .skip(1)
-            .map(|v| v.parse::<f32>().unwrap() / 100.)
-            .collect();
+    let params = parse_params(url)?;
+    query::check_params(&params, &query::TILE_PARAMS)?;
+    let query = Query::from_params(&params)?;
+    let params = query::resolve_times(params, &query);
 
-        let time = to_utc(
-            TIME.captures(&warning)
-                .unwrap()
-                .get(1)
-                .expect("Time parsing error")
-                .as_str(),
-            "%y%m%dT%H%MZ",
-        )?
-        .format("%c")
-        .to_string();
-
-        let (color, width) = warning_color(&warning);
-
-        path.push(path[0]);
-        path.push(path[1]);
-
-        writeln!(
-            &mut writer,
-            "Color: {color}\nLine: {width}, 0, \"Issued {time}\""
-        )
-        .unwrap();
-        for co in path.chunks_exact(2) {
-            writeln!(&mut writer, "{}, {}", co[0], -co[1]).unwrap()
+    let features_key = cache::key("/tiles", &params, &query::TILE_PARAMS);
+    let key = cache::key(&format!("/tiles/{z}/{x}/{y}"), &params, &query::TILE_PARAMS);
+    if let Some((body, content_type)) = cache::get(&key) {
+        return Ok(body_response(body.to_vec(), content_type));
+    }
+
+    let ttl = cache::ttl(&config, query.end());
+    let days = query.days();
+    let body = if config.async_threshold_days > 0 && days > config.async_threshold_days {
+        let keep = ttl.max(Duration::from_secs(config.job_ttl));
+        let tile = (z, x, y);
+        match mvt::tile_in_background(features_key, query, config.clone(), keep, tile, id) {
+            Some(body) => body,
+            None => {
+                let body = format!("Finding the warnings of {days} days, try again shortly.\n");
+                return Ok(response!(503, Cursor::new(body.into_bytes()))
+                    .with_header(Header::from_bytes("Retry-After", "5").unwrap()));
+            }
         }
-        writeln!(&mut writer, "End:\n").unwrap();
+    } else {
+        mvt::tile(&features_key, &query, &config, ttl, (z, x, y), id)?
+    };
+    cache::put(&config, key, body.clone(), CONTENT_TYPE, ttl);
+    Ok(body_response(body, CONTENT_TYPE))
+}
+
+/// Serves `/api/search?vtec=`, returning the products matching a full or partial VTEC code as JSON.
+///
+/// Only days the server has already read are searched, which includes a local archive once it has
+/// been indexed at startup, plus the range given by `start` and `end`.
+fn search(url: &str, config: Arc<config::Config>, id: &str) -> HttpResult<HttpResponse> {
+    let params = parse_params(url)?;
+    query::check_params(&params, query::SEARCH_PARAMS)?;
+    let search = params
+        .get("vtec")
+        .and_then(|code| index::normalize(code))
+        .ok_or_else(|| HttpError::Invalid("missing or invalid vtec".to_string()))?;
+
+    let mut days = index::search(&search);
+    if params.contains_key("start") || params.contains_key("end") {
+        let query = Query {
+            ranges: parse_times(&params)?,
+            ..Query::default()
+        };
+        days.extend(query.dates());
     }
 
-    println!("Done.");
+    let query = Query {
+        regex: Some(Regex::new(&regex::escape(&search)).unwrap()),
+        ..Query::default()
+    };
+    let days: Vec<_> = days.into_iter().collect();
+
+    run(days.len() as i64, "application/json", config, id, move |config, id| {
+        let mut warnings = Vec::new();
+        find_warnings_on(days, &query, config, id, |warning| {
+            let code = warning.vtec.as_ref().map(|vtec| vtec.code());
+            if code.is_some_and(|code| index::matches(&code, &search)) {
+                warnings.push(warning);
+            }
+        })?;
+        Ok(serde_json::to_vec(&warnings).unwrap())
+    })
+}
+
+/// Serves `/event/{year}/{wfo}/{etn}.txt` with a placefile of every product of one event, or
+/// `.json` with the event as JSON.
+///
+/// Without knowing when the event happened, its whole year is searched.
+fn event(path: &str, config: Arc<config::Config>, id: &str) -> HttpResult<HttpResponse> {
+    let (path, json) = match (path.strip_suffix(".txt"), path.strip_suffix(".json")) {
+        (Some(path), _) => (path, false),
+        (_, Some(path)) => (path, true),
+        _ => return Err(HttpError::NotFound),
+    };
+    let [year, wfo, etn] = path.split('/').collect::<Vec<_>>()[..] else {
+        return Err(HttpError::NotFound);
+    };
+
+    let year = year
+        .parse()
+        .map_err(|_| HttpError::Invalid(format!("invalid year: {year}, expected one like 2013")))?;
+    let etn = etn.parse().map_err(|_| {
+        HttpError::Invalid(format!("invalid event number: {etn}, expected one like 45"))
+    })?;
+    let query = Query::event(year, wfo, etn)?;
+    let days = query.days();
+
+    if !json {
+        return run(days, "text/plain", config, id, move |config, id| {
+            generate(&query, config, id)
+        });
+    }
+
+    run(days, "application/json", config, id, move |config, id| {
+        let mut warnings = Vec::new();
+        find_warnings(&query, config, id, |warning| warnings.push(warning))?;
+        let event = Event {
+            key: warnings.first().and_then(EventKey::of),
+            warnings,
+        };
+
+        match event.warnings.is_empty() {
+            true => Err(HttpError::NotFound),
+            false => Ok(event.to_json().into_bytes()),
+        }
+    })
+}
+
+/// Serves `/product/{id}`, rendering one product's polygons, or its raw text with `format=text`.
+fn product(
+    product: &str,
+    url: &str,
+    config: &config::Config,
+    id: &str,
+) -> HttpResult<HttpResponse> {
+    if product.is_empty() || !product.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+        let reason = format!("invalid product id: {product}, expected letters, digits and dashes");
+        return Err(HttpError::Invalid(reason));
+    }
+
+    let params = parse_params(url)?;
+    query::check_params(&params, query::PRODUCT_PARAMS)?;
+    let raw = match params.get("format").map(String::as_str) {
+        None | Some("placefile") => false,
+        Some("text") => true,
+        Some(format) => return Err(HttpError::Invalid(format!("invalid format: {format}"))),
+    };
+
+    println!("[{id}] Fetching product {product}");
+    let text = tokio::runtime::Runtime::new()
+        .unwrap()
+        .block_on(fetch::fetch_product(&fetch::client(config), config, product))?;
+
+    if raw {
+        return Ok(body_response(text.into_bytes(), "text/plain"));
+    }
+
+    let query = Query::default();
+    let mut writer = render::header(&query, config);
+    for warning in parse_day(&text, &query, id).0 {
+        render::warning(&mut writer, &warning, config, query.timefmt());
+    }
 
-    Ok(writer)
+    Ok(body_response(writer, "text/plain"))
+}
+
+/// Serves `/jobs/{id}`, returning the placefile once the job is done.
+fn job(job: &str, config: &config::Config) -> HttpResult<HttpResponse> {
+    match jobs::status(job, Duration::from_secs(config.job_ttl)) {
+        Some(jobs::Status::Running) => {
+            let body = format!("Job {job} is still running.\n");
+            Ok(response!(202, Cursor::new(body.into_bytes())))
+        }
+        Some(jobs::Status::Done(bytes, content_type)) => Ok(body_response(bytes, content_type)),
+        Some(jobs::Status::Failed) => Ok(response!(500, "server-error.html")),
+        None => Err(HttpError::NotFound),
+    }
 }
 
 /// Handles a request.
-fn handle_request(request: Request) {
-    let is_correct = request
-        .url()
-        .starts_with("/warnings.txt")
-        .then_some(Vec::<u8>::new())
-        .ok_or(HttpError::NotFound);
-
-    let result = is_correct
-        .and(parse_times(request.url()))
-        .and_then(find_warnings);
-
-    let response = match result {
-        Ok(bytes) => response!(200, Cursor::new(bytes)),
+fn handle_request(mut request: Request) {
+    let id = request_id(&request);
+    println!("[{id}] {} {}", request.method(), request.url());
+
+    let config = config::get();
+    let url = request.url().to_string();
+    let url = url.as_str();
+    let path = url.split('?').next().unwrap_or_default();
+
+    if path == "/ws" && config.live.enabled {
+        if let Some(accept) = live::accept_key(&request) {
+            println!("[{id}] Upgrading to websocket");
+            spawn_stream(request, id, move |request, id| {
+                live::serve_websocket(request, accept, id)
+            });
+            return;
+        }
+    }
+
+    if path == "/events" && config.live.enabled {
+        println!("[{id}] Streaming events");
+        spawn_stream(request, id, live::serve_events);
+        return;
+    }
+
+    let result = match path {
+        "/warnings.txt" if *request.method() == Method::Post => {
+            read_body(&mut request).and_then(|body| warnings(url, Some(&body), config, &id))
+        }
+        "/warnings.txt" => warnings(url, None, config, &id),
+        "/ws" if config.live.enabled => {
+            Err(HttpError::Invalid("expected a websocket upgrade".to_string()))
+        }
+        "/live.txt" if config.live.enabled => {
+            live::placefile(&config, &id).map(|body| body_response(body, "text/plain"))
+        }
+        "/api/search" => search(url, config, &id),
+        "/metrics" => Ok(body_response(
+            metrics::render().into_bytes(),
+            "text/plain; version=0.0.4",
+        )),
+        "/openapi.json" => Ok(body_response(
+            openapi::spec().to_string().into_bytes(),
+            "application/json",
+        )),
+        _ => {
+            if let Some(job_id) = path.strip_prefix("/jobs/") {
+                job(job_id, &config)
+            } else if let Some(tile_path) = path.strip_prefix("/tiles/") {
+                tile(tile_path, url, config.clone(), &id)
+            } else if let Some(product_id) = path.strip_prefix("/product/") {
+                product(product_id, url, &config, &id)
+            } else if let Some(event_path) = path.strip_prefix("/event/") {
+                event(event_path, config, &id)
+            } else {
+                Err(HttpError::NotFound)
+            }
+        }
+    };
+
+    let mut response = match result {
+        Ok(response) => response,
         Err(HttpError::NotFound) => response!(404, "not-found.html"),
         Err(HttpError::BadRequest) => response!(400, "bad-request.html"),
+        Err(HttpError::Invalid(reason)) => {
+            let body = format!("400 Bad Request: {reason}\n\n{}", query::usage(path));
+            response!(400, Cursor::new(body.into_bytes()))
+                .with_header(Header::from_bytes("Content-Type", "text/plain").unwrap())
+        }
         Err(e) => {
-            eprintln!("An unexpected error occured: {:?}", e);
+            eprintln!("[{id}] An unexpected error occured: {e}");
             response!(500, "server-error.html")
         }
     };
 
+    println!("[{id}] Responding {}", response.status_code().0);
+    response.add_header(Header::from_bytes("X-Request-Id", id).unwrap());
     request.respond(response).unwrap();
 }
 
+/// Commands run instead of the server.
+#[derive(Subcommand)]
+enum Command {
+    /// Download a range of days into a local archive ahead of time, for serving them offline.
+    ///
+    /// Days already downloaded are skipped, so an interrupted run can be resumed.
+    Index {
+        /// The first day to download.
+        #[arg(long)]
+        from: NaiveDate,
+        /// The last day to download.
+        #[arg(long)]
+        to: NaiveDate,
+        /// The directory to download into. Defaults to the configured local archive.
+        #[arg(long)]
+        dir: Option<PathBuf>,
+    },
+}
+
+/// Command line arguments.
+#[derive(Parser)]
+struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Path to the TOML config file, reloaded on SIGHUP.
+    #[arg(long, default_value = "tors_placefile.toml")]
+    config: PathBuf,
+
+    /// How many files to download at once, overriding the config.
+    #[arg(long)]
+    concurrency: Option<usize>,
+
+    /// Read `TOR_YYYYMMDD.txt` files from this directory instead of the upstreams.
+    #[arg(long)]
+    local_archive: Option<PathBuf>,
+
+    /// Detach from the terminal and run in the background. Unix only.
+    #[arg(long)]
+    daemon: bool,
+
+    /// Write the server's pid to this file, and remove it on shutdown.
+    #[arg(long)]
+    pidfile: Option<PathBuf>,
+
+    /// Append output to this file when running with --daemon, instead of discarding it.
+    #[arg(long, requires = "daemon")]
+    log_file: Option<PathBuf>,
+}
+
 fn main() {
-    let server = Server::http("localhost:8888").unwrap();
-    for request in server.incoming_requests() {
-        handle_request(request);
+    let args = Args::parse();
+    let overrides = config::Overrides {
+        concurrency: args.concurrency,
+        local_archive: args.local_archive,
+    };
+    if let Err(e) = config::init(args.config, overrides) {
+        eprintln!("Failed to load config: {e}");
+        std::process::exit(1);
+    }
+
+    if let Some(Command::Index { from, to, dir }) = args.command {
+        let config = config::get();
+        let Some(dir) = dir.or_else(|| config.local_archive.clone()) else {
+            eprintln!("Give a directory with --dir, or set local_archive in the config");
+            std::process::exit(1);
+        };
+        let failed = archive::backfill(&config, &dir, from, to);
+        if failed > 0 {
+            eprintln!("{failed} days failed to download, run again to retry them");
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if args.daemon {
+        if let Err(e) = daemon::daemonize(args.log_file.as_deref()) {
+            eprintln!("Failed to start in the background: {e}");
+            std::process::exit(1);
+        }
+    }
+    if let Some(path) = &args.pidfile {
+        if let Err(e) = daemon::write_pidfile(path) {
+            eprintln!("Failed to write pidfile {}: {e}", path.display());
+            std::process::exit(1);
+        }
+    }
+    daemon::exit_on_signal(args.pidfile);
+
+    config::watch_sighup();
+    live::start();
+    publish::start();
+    webhook::start();
+    verify::load(&config::get());
+    if let Some(dir) = &config::get().local_archive {
+        index::load(dir.clone());
+    }
+
+    let listener = std::net::TcpListener::bind("localhost:8888").unwrap();
+    if let Err(e) = set_read_timeout(&listener, live::READ_TIMEOUT) {
+        eprintln!("Failed to set the read timeout, dead websockets will stay open: {e}");
+    }
+    let server = Arc::new(Server::from_listener(listener, None).unwrap());
+    let workers = (0..config::get().workers.max(1))
+        .map(|_| {
+            let server = server.clone();
+            std::thread::spawn(move || {
+                for request in server.incoming_requests() {
+                    // A panic drops the request, which closes its connection, and keeps the worker.
+                    let handled = std::panic::catch_unwind(AssertUnwindSafe(|| {
+                        handle_request(request)
+                    }));
+                    if handled.is_err() {
+                        eprintln!("Request handler panicked");
+                    }
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+
+    for worker in workers {
+        let _ = worker.join();
+    }
+}
+
+/// Sets the read timeout of every connection a listener accepts, which inherit it from the
+/// listener.
+///
+/// tiny_http doesn't hand out the socket of an upgraded connection, so this is the only way to stop
+/// a websocket whose client vanished from blocking its thread forever.
+#[cfg(unix)]
+fn set_read_timeout(listener: &std::net::TcpListener, timeout: Duration) -> std::io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    let timeout = libc::timeval {
+        tv_sec: timeout.as_secs() as libc::time_t,
+        tv_usec: 0,
+    };
+    let result = unsafe {
+        libc::setsockopt(
+            listener.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_RCVTIMEO,
+            &timeout as *const libc::timeval as *const libc::c_void,
+            std::mem::size_of::<libc::timeval>() as libc::socklen_t,
+        )
+    };
+
+    match result {
+        0 => Ok(()),
+        _ => Err(std::io::Error::last_os_error()),
+    }
+}
+
+#[cfg(not(unix))]
+fn set_read_timeout(_listener: &std::net::TcpListener, _timeout: Duration) -> std::io::Result<()> {
+    Err(std::io::Error::other("only supported on Unix"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn params(query: &str) -> BTreeMap<String, String> {
+        parse_params(&format!("/warnings.txt?{query}")).unwrap()
+    }
+
+    fn utc(y: i32, m: u32, d: u32, h: u32, min: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(y, m, d, h, min, 0).unwrap()
+    }
+
+    #[test]
+    fn offsets() {
+        let hours = |s: &str| parse_offset(s).map(|offset| offset.local_minus_utc() / 3600);
+
+        assert_eq!(hours("UTC").unwrap(), 0);
+        assert_eq!(hours("cdt").unwrap(), -5);
+        assert_eq!(hours("-05:00").unwrap(), -5);
+        assert_eq!(hours("-0500").unwrap(), -5);
+        assert_eq!(hours("-5").unwrap(), -5);
+        // An unencoded plus arrives as a space.
+        assert_eq!(hours(" 09:00").unwrap(), 9);
+        assert_eq!(parse_offset("+05:30").unwrap().local_minus_utc(), 5 * 3600 + 30 * 60);
+
+        assert!(parse_offset("+15").is_err());
+        assert!(parse_offset("-05:60").is_err());
+        assert!(parse_offset("Central").is_err());
+    }
+
+    #[test]
+    fn whole_days() {
+        let ranges = parse_times(&params("start=2024-05-20&end=2024-05-21")).unwrap();
+
+        assert_eq!(ranges, [Range::days(utc(2024, 5, 20, 0, 0), utc(2024, 5, 21, 0, 0))]);
+    }
+
+    #[test]
+    fn days_in_an_offset() {
+        let ranges = parse_times(&params("start=2024-05-20&end=2024-05-20&tz=CDT")).unwrap();
+
+        let start = utc(2024, 5, 20, 5, 0);
+        let end = utc(2024, 5, 21, 4, 59) + Duration::from_secs(59);
+        assert_eq!((ranges[0].after, ranges[0].before), (Some(start), Some(end)));
+    }
+
+    #[test]
+    fn iso_times() {
+        let ranges = parse_times(&params("start=2024-05-20T21:00Z&end=2024-05-20T17:00-05:00"));
+        let ranges = ranges.unwrap();
+
+        assert_eq!(ranges[0].after, Some(utc(2024, 5, 20, 21, 0)));
+        assert_eq!(ranges[0].before, Some(utc(2024, 5, 20, 22, 0)));
+    }
+
+    #[test]
+    fn epoch_times() {
+        let ranges = parse_times(&params("start=1716238800&end=2024-05-20T22:00Z")).unwrap();
+
+        assert_eq!(ranges[0].after, Some(utc(2024, 5, 20, 21, 0)));
+        assert_eq!(ranges[0].before, Some(utc(2024, 5, 20, 22, 0)));
+    }
+
+    #[test]
+    fn several_ranges_in_order() {
+        let query = "range=2013-05-20/2013-05-21&range=2011-04-27/2011-04-28";
+        let starts: Vec<_> = parse_times(&params(query))
+            .unwrap()
+            .iter()
+            .map(|range| range.start)
+            .collect();
+
+        assert_eq!(starts, [utc(2011, 4, 27, 0, 0), utc(2013, 5, 20, 0, 0)]);
+    }
+
+    #[test]
+    fn invalid_times() {
+        assert!(parse_times(&params("start=2024-05-20")).is_err());
+        assert!(parse_times(&params("start=May 20&end=2024-05-20")).is_err());
+        assert!(parse_times(&params("start=2024-05-20T25:00Z&end=2024-05-21")).is_err());
+    }
+
+    #[test]
+    fn invalid_offsets() {
+        assert!(parse_times(&params("start=2024-05-20&end=2024-05-20&tz=Central")).is_err());
+    }
+
+    #[test]
+    fn invalid_ranges() {
+        assert!(parse_times(&params("range=2024-05-20")).is_err());
+        assert!(parse_times(&params("range=2024-05-20/2024-05-21&start=2024-05-20")).is_err());
+    }
+
+    #[test]
+    fn fixture_day() {
+        let fixtures = PathBuf::from(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures"));
+        let config = config::Config {
+            local_archive: Some(fixtures),
+            ..Default::default()
+        };
+        let date = NaiveDate::from_ymd_opt(2024, 5, 20).unwrap();
+        let client = fetch::client(&config);
+        let text = tokio::runtime::Runtime::new()
+            .unwrap()
+            .block_on(fetch::fetch_day(&client, &config, date, "test"))
+            .unwrap();
+
+        let (warnings, skips) = parse_day(&text, &Query::default(), "test");
+
+        let etns: Vec<_> = warnings.iter().map(|w| w.vtec.as_ref().unwrap().etn).collect();
+        assert_eq!(etns, [20, 20, 3]);
+        let expected = [
+            (Skip::NoPolygon, 1),
+            (Skip::BadTime, 1),
+            (Skip::InvalidUtf8, 1),
+            (Skip::InvalidPolygon, 1),
+        ];
+        assert_eq!(skips, Skips::from(expected));
     }
 }
@@ -0,0 +1,48 @@
+use crate::warning::{Skip, Skips};
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Segments skipped since the server started, indexed by reason.
+static SKIPPED: [AtomicU64; Skip::ALL.len()] = [const { AtomicU64::new(0) }; Skip::ALL.len()];
+/// Warnings parsed since the server started.
+static PARSED: AtomicU64 = AtomicU64::new(0);
+
+/// Adds the outcome of parsing some segments to the totals.
+pub fn record(parsed: usize, skips: &Skips) {
+    PARSED.fetch_add(parsed as u64, Ordering::Relaxed);
+    for (&skip, &count) in skips {
+        SKIPPED[skip as usize].fetch_add(count as u64, Ordering::Relaxed);
+    }
+}
+
+/// Renders the totals in the Prometheus text format, for `/metrics`.
+pub fn render() -> String {
+    let mut text = String::new();
+
+    writeln!(
+        text,
+        "# HELP tors_placefile_parsed_warnings_total Warnings parsed."
+    )
+    .unwrap();
+    writeln!(text, "# TYPE tors_placefile_parsed_warnings_total counter").unwrap();
+    let parsed = PARSED.load(Ordering::Relaxed);
+    writeln!(text, "tors_placefile_parsed_warnings_total {parsed}").unwrap();
+
+    writeln!(
+        text,
+        "# HELP tors_placefile_skipped_segments_total Product segments left out, by reason."
+    )
+    .unwrap();
+    writeln!(text, "# TYPE tors_placefile_skipped_segments_total counter").unwrap();
+    for skip in Skip::ALL {
+        let count = SKIPPED[skip as usize].load(Ordering::Relaxed);
+        let reason = skip.name();
+        writeln!(
+            text,
+            "tors_placefile_skipped_segments_total{{reason=\"{reason}\"}} {count}"
+        )
+        .unwrap();
+    }
+
+    text
+}
@@ -0,0 +1,518 @@
+use crate::config::Config;
+use crate::geo;
+use crate::query::Query;
+use crate::warning::Warning;
+use crate::HttpResult;
+use lazy_static::lazy_static;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+/// The size of a tile in its own coordinates.
+const EXTENT: u32 = 4096;
+/// How far polygons are drawn past the edge of a tile, so outlines don't show at the seams.
+const BUFFER: f64 = 64.;
+/// The deepest zoom level served.
+pub const MAX_ZOOM: u8 = 22;
+/// The property names of each feature, in the order their values are stored.
+const KEYS: [&str; 7] = [
+    "issued",
+    "severity",
+    "observed",
+    "waterspout",
+    "verified",
+    "source",
+    "vtec",
+];
+
+/// A property value of a feature.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Value {
+    String(String),
+    Bool(bool),
+}
+
+/// A warning projected for tiling.
+struct Feature {
+    /// The polygon in world coordinates, without its closing point.
+    ring: Vec<(f64, f64)>,
+    /// The smallest and largest x and y of the ring.
+    bounds: ((f64, f64), (f64, f64)),
+    /// The value of each key in `KEYS`, if the warning has one.
+    values: [Option<Value>; KEYS.len()],
+}
+
+impl Feature {
+    fn new(warning: &Warning) -> Self {
+        let points = &warning.polygon[..warning.polygon.len() - 1];
+        let ring: Vec<_> = points.iter().map(|&point| geo::world(point)).collect();
+        let bounds = ring.iter().fold(
+            ((f64::MAX, f64::MAX), (f64::MIN, f64::MIN)),
+            |((min_x, min_y), (max_x, max_y)), &(x, y)| {
+                ((min_x.min(x), min_y.min(y)), (max_x.max(x), max_y.max(y)))
+            },
+        );
+
+        let severity = serde_json::to_value(warning.severity).unwrap();
+        let values = [
+            Some(Value::String(
+                warning.issued.format("%Y-%m-%dT%H:%M:%SZ").to_string(),
+            )),
+            severity.as_str().map(|s| Value::String(s.to_string())),
+            Some(Value::Bool(warning.observed)),
+            Some(Value::Bool(warning.waterspout)),
+            warning.verified.map(Value::Bool),
+            warning.source.clone().map(Value::String),
+            warning.vtec.as_ref().map(|vtec| Value::String(vtec.code())),
+        ];
+
+        Self {
+            ring,
+            bounds,
+            values,
+        }
+    }
+}
+
+/// The features of a query, shared by every tile requested with it.
+struct Entry {
+    features: Arc<Vec<Feature>>,
+    expires: Instant,
+}
+
+/// The features of a query while they are being found, which every tile asking for them meanwhile
+/// waits on instead of finding them again.
+type Building = Arc<Mutex<Option<Arc<Vec<Feature>>>>>;
+
+lazy_static! {
+    static ref FEATURES: Mutex<HashMap<String, Entry>> = Mutex::new(HashMap::new());
+    static ref BUILDING: Mutex<HashMap<String, Building>> = Mutex::new(HashMap::new());
+}
+
+/// Returns the features found for an earlier tile with the same query, if they haven't expired.
+fn cached(key: &str) -> Option<Arc<Vec<Feature>>> {
+    let cache = FEATURES.lock().unwrap();
+    let entry = cache.get(key)?;
+    (entry.expires > Instant::now()).then(|| entry.features.clone())
+}
+
+/// Joins the search for a query's features, returning it and whether no one else had started it.
+fn join(key: &str) -> (Building, bool) {
+    let mut building = BUILDING.lock().unwrap();
+    let first = !building.contains_key(key);
+    (building.entry(key.to_string()).or_default().clone(), first)
+}
+
+/// Finds the features of a query once for everyone who joined the search, keeping them in the
+/// cache for `ttl` if it holds up to `max_entries`.
+fn build(
+    key: &str,
+    building: Building,
+    query: &Query,
+    config: &Config,
+    (ttl, max_entries): (Duration, usize),
+    id: &str,
+) -> HttpResult<Arc<Vec<Feature>>> {
+    // A search that panicked left nothing behind, so the next one to get here starts over.
+    let mut found = building.lock().unwrap_or_else(PoisonError::into_inner);
+    if let Some(features) = found.as_ref() {
+        return Ok(features.clone());
+    }
+
+    let mut features = Vec::new();
+    let result = crate::select_warnings(query, config, id, |warning| {
+        features.push(Feature::new(&warning))
+    });
+    let result = result.map(|_| Arc::new(features));
+    if let Ok(features) = &result {
+        *found = Some(features.clone());
+        store(key, features.clone(), ttl, max_entries);
+    }
+
+    let mut searches = BUILDING.lock().unwrap();
+    if searches.get(key).is_some_and(|search| Arc::ptr_eq(search, &building)) {
+        searches.remove(key);
+    }
+    result
+}
+
+/// Keeps the features of a query in the cache, dropping the ones expiring soonest to make room.
+fn store(key: &str, features: Arc<Vec<Feature>>, ttl: Duration, max_entries: usize) {
+    if max_entries > 0 && !ttl.is_zero() {
+        let mut cache = FEATURES.lock().unwrap();
+        let now = Instant::now();
+        cache.retain(|_, entry| entry.expires > now);
+        while cache.len() >= max_entries {
+            let soonest = cache
+                .iter()
+                .min_by_key(|(_, entry)| entry.expires)
+                .map(|(key, _)| key.clone())
+                .unwrap();
+            cache.remove(&soonest);
+        }
+
+        cache.insert(
+            key.to_string(),
+            Entry {
+                features,
+                expires: now + ttl,
+            },
+        );
+    }
+}
+
+/// Finds the warnings matching a query and projects them, reusing the ones found for an earlier
+/// tile with the same query, or waiting for the ones being found for a tile requested alongside.
+fn features(
+    key: &str,
+    query: &Query,
+    config: &Config,
+    ttl: Duration,
+    id: &str,
+) -> HttpResult<Arc<Vec<Feature>>> {
+    if let Some(features) = cached(key) {
+        return Ok(features);
+    }
+
+    let (building, _) = join(key);
+    build(key, building, query, config, (ttl, config.cache.max_entries), id)
+}
+
+/// Drops every cached set of features, such as when the config changes.
+pub fn clear() {
+    FEATURES.lock().unwrap().clear();
+}
+
+/// Clips a ring to the edges of a box with Sutherland-Hodgman.
+fn clip(ring: Vec<(f64, f64)>, min: f64, max: f64) -> Vec<(f64, f64)> {
+    // Each edge as a test for being inside it, and the point where a segment crosses it.
+    type Inside = fn((f64, f64), f64) -> bool;
+    type Cross = fn((f64, f64), (f64, f64), f64) -> (f64, f64);
+    let cross_x: Cross = |a, b, x| (x, a.1 + (b.1 - a.1) * (x - a.0) / (b.0 - a.0));
+    let cross_y: Cross = |a, b, y| (a.0 + (b.0 - a.0) * (y - a.1) / (b.1 - a.1), y);
+    let edges: [(Inside, Cross, f64); 4] = [
+        (|p, x| p.0 >= x, cross_x, min),
+        (|p, x| p.0 <= x, cross_x, max),
+        (|p, y| p.1 >= y, cross_y, min),
+        (|p, y| p.1 <= y, cross_y, max),
+    ];
+
+    edges.iter().fold(ring, |ring, &(inside, cross, edge)| {
+        let mut clipped = Vec::with_capacity(ring.len() + 4);
+        for (i, &b) in ring.iter().enumerate() {
+            let a = ring[(i + ring.len() - 1) % ring.len()];
+            match (inside(a, edge), inside(b, edge)) {
+                (true, true) => clipped.push(b),
+                (true, false) => clipped.push(cross(a, b, edge)),
+                (false, true) => clipped.extend([cross(a, b, edge), b]),
+                (false, false) => (),
+            }
+        }
+        clipped
+    })
+}
+
+/// Appends a varint.
+fn varint(buffer: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buffer.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buffer.push(value as u8);
+}
+
+/// Appends a field holding a varint.
+fn uint_field(buffer: &mut Vec<u8>, field: u32, value: u64) {
+    varint(buffer, u64::from(field << 3));
+    varint(buffer, value);
+}
+
+/// Appends a field holding bytes, such as a string or an embedded message.
+fn bytes_field(buffer: &mut Vec<u8>, field: u32, bytes: &[u8]) {
+    varint(buffer, u64::from(field << 3 | 2));
+    varint(buffer, bytes.len() as u64);
+    buffer.extend_from_slice(bytes);
+}
+
+/// Appends a field holding packed varints.
+fn packed_field(buffer: &mut Vec<u8>, field: u32, values: &[u32]) {
+    let mut packed = Vec::new();
+    for &value in values {
+        varint(&mut packed, u64::from(value));
+    }
+    bytes_field(buffer, field, &packed);
+}
+
+/// Encodes a ring in tile coordinates as geometry commands.
+///
+/// Returns nothing if the ring collapses once rounded to whole tile units.
+fn geometry(ring: &[(f64, f64)]) -> Option<Vec<u32>> {
+    let mut points: Vec<(i32, i32)> = ring
+        .iter()
+        .map(|&(x, y)| (x.round() as i32, y.round() as i32))
+        .collect();
+    points.dedup();
+    while points.len() > 1 && points.first() == points.last() {
+        points.pop();
+    }
+    if points.len() < 3 {
+        return None;
+    }
+
+    // Exterior rings go clockwise on screen, which is a positive area with y growing down.
+    let area: i64 = (0..points.len())
+        .map(|i| {
+            let (a, b) = (points[i], points[(i + 1) % points.len()]);
+            i64::from(a.0) * i64::from(b.1) - i64::from(b.0) * i64::from(a.1)
+        })
+        .sum();
+    match area {
+        0 => return None,
+        area if area < 0 => points.reverse(),
+        _ => (),
+    }
+
+    let zigzag = |n: i32| ((n << 1) ^ (n >> 31)) as u32;
+    let command = |id: u32, count: usize| id | (count as u32) << 3;
+
+    let mut commands = vec![command(1, 1)];
+    let mut cursor = (0, 0);
+    for (i, &(x, y)) in points.iter().enumerate() {
+        if i == 1 {
+            commands.push(command(2, points.len() - 1));
+        }
+        commands.extend([zigzag(x - cursor.0), zigzag(y - cursor.1)]);
+        cursor = (x, y);
+    }
+    commands.push(command(7, 1));
+
+    Some(commands)
+}
+
+/// Encodes a property value.
+fn value(value: &Value) -> Vec<u8> {
+    let mut buffer = Vec::new();
+    match value {
+        Value::String(s) => bytes_field(&mut buffer, 1, s.as_bytes()),
+        Value::Bool(b) => uint_field(&mut buffer, 7, u64::from(*b)),
+    }
+    buffer
+}
+
+/// Encodes the warnings over one tile as a Mapbox Vector Tile with a single `warnings` layer.
+fn encode(features: &[Feature], z: u8, x: u32, y: u32) -> Vec<u8> {
+    let scale = f64::from(1u32 << z) * f64::from(EXTENT);
+    let origin = (
+        f64::from(x) * f64::from(EXTENT),
+        f64::from(y) * f64::from(EXTENT),
+    );
+    let to_tile = |(wx, wy): (f64, f64)| (wx * scale - origin.0, wy * scale - origin.1);
+    let (min, max) = (-BUFFER, f64::from(EXTENT) + BUFFER);
+
+    let mut layer = Vec::new();
+    let mut values: Vec<&Value> = Vec::new();
+    let mut indices: HashMap<&Value, u32> = HashMap::new();
+
+    for (id, feature) in features.iter().enumerate() {
+        let ((min_x, min_y), (max_x, max_y)) = feature.bounds;
+        let (left, top) = to_tile((min_x, min_y));
+        let (right, bottom) = to_tile((max_x, max_y));
+        if right < min || left > max || bottom < min || top > max {
+            continue;
+        }
+
+        let ring = feature.ring.iter().map(|&point| to_tile(point)).collect();
+        let Some(geometry) = geometry(&clip(ring, min, max)) else {
+            continue;
+        };
+
+        let mut tags = Vec::new();
+        for (key, value) in feature.values.iter().enumerate() {
+            let Some(value) = value else { continue };
+            let index = *indices.entry(value).or_insert_with(|| {
+                values.push(value);
+                values.len() as u32 - 1
+            });
+            tags.extend([key as u32, index]);
+        }
+
+        let mut encoded = Vec::new();
+        uint_field(&mut encoded, 1, id as u64 + 1);
+        packed_field(&mut encoded, 2, &tags);
+        // The geometry type, 3 for polygons.
+        uint_field(&mut encoded, 3, 3);
+        packed_field(&mut encoded, 4, &geometry);
+        bytes_field(&mut layer, 2, &encoded);
+    }
+
+    if values.is_empty() {
+        return Vec::new();
+    }
+
+    for key in KEYS {
+        bytes_field(&mut layer, 3, key.as_bytes());
+    }
+    for v in values {
+        bytes_field(&mut layer, 4, &value(v));
+    }
+
+    let mut tile = Vec::new();
+    let mut header = Vec::new();
+    uint_field(&mut header, 15, 2);
+    bytes_field(&mut header, 1, b"warnings");
+    uint_field(&mut header, 5, u64::from(EXTENT));
+    header.extend(layer);
+    bytes_field(&mut tile, 3, &header);
+
+    tile
+}
+
+/// Renders one tile of the warnings matching a query.
+///
+/// `key` identifies the query, so the warnings found for one tile are reused for the others.
+pub fn tile(
+    key: &str,
+    query: &Query,
+    config: &Config,
+    ttl: Duration,
+    (z, x, y): (u8, u32, u32),
+    id: &str,
+) -> HttpResult<Vec<u8>> {
+    let features = features(key, query, config, ttl, id)?;
+    Ok(encode(&features, z, x, y))
+}
+
+/// Encodes one tile of a long range once its warnings have been found, starting to find them on
+/// a background thread if no one has yet.
+///
+/// The warnings are kept for `keep` even when the cache is disabled, since every tile of the range
+/// needs them. Returns `None` while they are being found.
+pub fn tile_in_background(
+    key: String,
+    query: Query,
+    config: Arc<Config>,
+    keep: Duration,
+    (z, x, y): (u8, u32, u32),
+    id: &str,
+) -> Option<Vec<u8>> {
+    if let Some(features) = cached(&key) {
+        return Some(encode(&features, z, x, y));
+    }
+
+    let (building, first) = join(&key);
+    if first {
+        let id = id.to_string();
+        let max_entries = config.cache.max_entries.max(1);
+        std::thread::spawn(move || {
+            println!("[{id}] Finding the warnings of {} days for tiles", query.days());
+            if let Err(e) = build(&key, building, &query, &config, (keep, max_entries), &id) {
+                eprintln!("[{id}] Failed to find the warnings for tiles: {e}");
+            }
+        });
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Reads a varint, advancing past it.
+    fn read_varint(bytes: &mut &[u8]) -> u64 {
+        let mut value = 0;
+        for shift in (0..64).step_by(7) {
+            let (&byte, rest) = bytes.split_first().unwrap();
+            *bytes = rest;
+            value |= u64::from(byte & 0x7f) << shift;
+            if byte < 0x80 {
+                break;
+            }
+        }
+        value
+    }
+
+    /// Splits a message into its fields, as (field, varint value or bytes).
+    fn fields(mut bytes: &[u8]) -> Vec<(u64, Result<u64, Vec<u8>>)> {
+        let mut fields = Vec::new();
+        while !bytes.is_empty() {
+            let key = read_varint(&mut bytes);
+            let value = match key & 7 {
+                0 => Ok(read_varint(&mut bytes)),
+                2 => {
+                    let len = read_varint(&mut bytes) as usize;
+                    let (value, rest) = bytes.split_at(len);
+                    bytes = rest;
+                    Err(value.to_vec())
+                }
+                wire => panic!("unexpected wire type {wire}"),
+            };
+            fields.push((key >> 3, value));
+        }
+        fields
+    }
+
+    fn warning() -> Warning {
+        Warning::parse(
+            "WFUS54 KOUN 202101\nTOROUN\nOKC017-202145-\n\
+             /O.NEW.KOUN.TO.W.0020.240520T2101Z-240520T2145Z/\n\n\
+             LAT...LON 3526 9768 3537 9768 3538 9742 3531 9738\n\nTORNADO...OBSERVED\n",
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn varints() {
+        let mut buffer = Vec::new();
+        varint(&mut buffer, 1);
+        varint(&mut buffer, 300);
+        assert_eq!(buffer, [0x01, 0xac, 0x02]);
+    }
+
+    #[test]
+    fn square_geometry() {
+        let square = [(0., 0.), (10., 0.), (10., 10.), (0., 10.)];
+        assert_eq!(geometry(&square).unwrap(), [9, 0, 0, 26, 20, 0, 0, 20, 19, 0, 15]);
+
+        // Counter-clockwise rings are turned around, since they would be holes.
+        let reversed: Vec<_> = square.iter().rev().copied().collect();
+        assert_eq!(geometry(&reversed), geometry(&square));
+
+        // Rings that round to a line are left out.
+        assert_eq!(geometry(&[(0., 0.), (0.2, 0.2), (10., 10.)]), None);
+    }
+
+    #[test]
+    fn clipping() {
+        let ring = vec![(-10., 5.), (5., 5.), (5., -10.)];
+        let clipped = clip(ring, 0., 100.);
+
+        let inside = |v: f64| (0. ..=100.).contains(&v);
+        assert!(clipped.iter().all(|&(x, y)| inside(x) && inside(y)));
+        assert!(clipped.contains(&(5., 5.)));
+    }
+
+    #[test]
+    fn tiles() {
+        let features = [Feature::new(&warning())];
+        // The zoom 6 tile over central Oklahoma.
+        let tile = encode(&features, 6, 14, 25);
+
+        let [(3, Err(layer))] = &fields(&tile)[..] else {
+            panic!("expected one layer");
+        };
+        let layer = fields(layer);
+        assert_eq!(layer[0], (15, Ok(2)));
+        assert_eq!(layer[1], (1, Err(b"warnings".to_vec())));
+        assert_eq!(layer[2], (5, Ok(u64::from(EXTENT))));
+
+        let count = |field| layer.iter().filter(|(f, _)| *f == field).count();
+        assert_eq!(count(2), 1);
+        assert_eq!(count(3), KEYS.len());
+        // The issue time, severity, observed, waterspout and VTEC code, but no verification or
+        // source.
+        assert_eq!(count(4), 5);
+
+        // Tiles the warning isn't over are empty.
+        assert!(encode(&features, 6, 0, 0).is_empty());
+    }
+}
@@ -0,0 +1,169 @@
+use crate::query::{
+    Format, Inner, Kind, Param, Split, PARAMS, PRODUCT_PARAMS, SEARCH_PARAMS, TILE_PARAMS,
+};
+use serde_json::{json, Map, Value};
+
+/// Describes a parameter as an OpenAPI parameter object.
+fn parameter(param: &Param, location: &str) -> Value {
+    let mut schema = match param.kind {
+        Kind::Text => json!({ "type": "string" }),
+        Kind::Time => json!({
+            "type": "string",
+            "anyOf": [
+                { "format": "date" },
+                { "format": "date-time" },
+                { "pattern": "^([0-9]+|today|yesterday|this_week)$" },
+            ],
+        }),
+        Kind::Range => json!({ "type": "string", "pattern": "^[^/]+/[^/]+$" }),
+        Kind::Number => json!({ "type": "number" }),
+        Kind::Choice(values) => json!({ "type": "string", "enum": values }),
+    };
+    if let Some(default) = param.default {
+        schema["default"] = json!(default);
+    }
+    if param.repeatable {
+        schema = json!({ "type": "array", "items": schema });
+    }
+
+    json!({
+        "name": param.name,
+        "in": location,
+        "description": param.description,
+        "required": param.required || location == "path",
+        "schema": schema,
+    })
+}
+
+/// Describes a GET operation with its parameters and the content types of a successful response.
+fn get(summary: &str, path: &[Param], query: &[Param], content_types: &[&str]) -> Value {
+    let parameters: Vec<_> = path
+        .iter()
+        .map(|param| parameter(param, "path"))
+        .chain(query.iter().map(|param| parameter(param, "query")))
+        .collect();
+    let content: Map<_, _> = content_types
+        .iter()
+        .map(|content_type| (content_type.to_string(), json!({})))
+        .collect();
+
+    json!({
+        "get": {
+            "summary": summary,
+            "parameters": parameters,
+            "responses": {
+                "200": { "description": "OK", "content": content },
+                "202": { "description": "Generating in the background, poll /jobs/{id}." },
+                "400": { "description": "An invalid parameter, explained in plain text." },
+                "404": { "description": "Not found." },
+            },
+        }
+    })
+}
+
+/// Builds the OpenAPI description of the server's endpoints.
+///
+/// The parameters come from the same lists the endpoints check requests against, so the two can't
+/// drift apart.
+pub fn spec() -> Value {
+    let formats = [
+        Format::Placefile,
+        Format::Zip(Split::Day, Inner::Placefile),
+        Format::GeoJson,
+        Format::Csv,
+    ]
+    .map(Format::content_type);
+
+    let id = Param::new("id", Kind::Text, "The id returned when the job started.");
+    let product = Param::new("id", Kind::Text, "An IEM product id.");
+    let event = [
+        Param::new("year", Kind::Number, "The year the event was issued in."),
+        Param::new(
+            "wfo",
+            Kind::Text,
+            "The issuing office, such as OUN or KOUN.",
+        ),
+        Param::new("etn", Kind::Number, "The VTEC event tracking number."),
+    ];
+    let tile = [
+        Param::new("z", Kind::Number, "The zoom level, up to 22."),
+        Param::new("x", Kind::Number, "The column of the tile."),
+        Param::new("y", Kind::Number, "The row of the tile, from the north."),
+    ];
+
+    // POSTing GeoJSON takes the same parameters, and keeps the warnings intersecting its polygons.
+    let mut warnings = get("Warnings issued in a range of days", &[], PARAMS, &formats);
+    let mut post = warnings["get"].clone();
+    post["summary"] = json!("Warnings issued in a range of days, within GeoJSON polygons");
+    post["requestBody"] = json!({
+        "required": true,
+        "content": { "application/geo+json": { "schema": { "type": "object" } } },
+    });
+    warnings["post"] = post;
+
+    let paths = [
+        ("/warnings.txt", warnings),
+        (
+            "/tiles/{z}/{x}/{y}.mvt",
+            get(
+                "Warnings over one map tile",
+                &tile,
+                &TILE_PARAMS,
+                &["application/vnd.mapbox-vector-tile"],
+            ),
+        ),
+        (
+            "/jobs/{id}",
+            get("The result of a background job", &[id], &[], &formats),
+        ),
+        (
+            "/product/{id}",
+            get(
+                "One product's polygons",
+                &[product],
+                PRODUCT_PARAMS,
+                &["text/plain"],
+            ),
+        ),
+        (
+            "/event/{year}/{wfo}/{etn}.txt",
+            get("Every product of one event", &event, &[], &["text/plain"]),
+        ),
+        (
+            "/event/{year}/{wfo}/{etn}.json",
+            get("One event as JSON", &event, &[], &["application/json"]),
+        ),
+        (
+            "/api/search",
+            get(
+                "Products matching a VTEC code",
+                &[],
+                SEARCH_PARAMS,
+                &["application/json"],
+            ),
+        ),
+        (
+            "/live.txt",
+            get(
+                "Warnings in effect now, in live mode",
+                &[],
+                &[],
+                &["text/plain"],
+            ),
+        ),
+        (
+            "/openapi.json",
+            get("This description", &[], &[], &["application/json"]),
+        ),
+    ];
+
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": env!("CARGO_PKG_NAME"),
+            "version": env!("CARGO_PKG_VERSION"),
+            "description": "Archived tornado warnings as placefiles, GeoJSON, CSV and JSON.",
+        },
+        "paths": Map::from_iter(paths.map(|(path, item)| (path.to_string(), item))),
+    })
+}
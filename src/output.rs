@@ -0,0 +1,286 @@
+use crate::config::Config;
+use crate::event::Event;
+use crate::query::{Format, Inner, Mode, Query, Range, Split};
+use crate::warning::{Severity, Skips, Warning};
+use crate::{geojson, render};
+use chrono::{DateTime, Duration, Utc};
+use serde::Serialize;
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::io::{Cursor, Write};
+
+/// The body being built, in one of the formats.
+enum Body {
+    Placefile(Vec<u8>),
+    /// Files keyed by the day or month they cover, the strftime format of the keys, and the
+    /// format of the files.
+    Zip(BTreeMap<String, Body>, &'static str, Inner),
+    /// The feature collection, and whether no feature has been written yet.
+    GeoJson(Vec<u8>, bool),
+    Csv(Box<csv::Writer<Vec<u8>>>),
+}
+
+impl Body {
+    /// Starts an empty file.
+    fn file(format: Inner, query: &Query, config: &Config) -> Self {
+        match format {
+            Inner::Placefile => Body::Placefile(render::header(query, config)),
+            Inner::GeoJson => Body::GeoJson(geojson::header(query.crs), true),
+            Inner::Csv => Body::Csv(Box::new(csv::Writer::from_writer(Vec::new()))),
+        }
+    }
+
+    /// Returns the file that something issued at `time` belongs in, which is the body itself
+    /// unless it is a zip.
+    fn file_for(&mut self, time: DateTime<Utc>, query: &Query, config: &Config) -> &mut Body {
+        match self {
+            Body::Zip(files, format, inner) => files
+                .entry(time.format(format).to_string())
+                .or_insert_with(|| Body::file(*inner, query, config)),
+            body => body,
+        }
+    }
+
+    /// Finishes a file, or packs a zip's files into it.
+    fn finish(self) -> Vec<u8> {
+        match self {
+            Body::Placefile(writer) => writer,
+            Body::Zip(files, _, inner) => zip_files(files, inner),
+            Body::GeoJson(mut writer, _) => {
+                geojson::footer(&mut writer);
+                writer
+            }
+            Body::Csv(writer) => writer.into_inner().unwrap(),
+        }
+    }
+}
+
+/// A warning as a CSV row.
+#[derive(Serialize)]
+struct Row<'a> {
+    issued: DateTime<Utc>,
+    expires: Option<DateTime<Utc>>,
+    office: Option<&'a str>,
+    etn: Option<u32>,
+    action: Option<&'a str>,
+    severity: Severity,
+    observed: bool,
+    waterspout: bool,
+    verified: Option<bool>,
+    max_hail_size: Option<f32>,
+    max_wind_gust: Option<u32>,
+    source: Option<&'a str>,
+    /// The polygon as WKT, in longitude and latitude.
+    polygon: String,
+}
+
+impl<'a> Row<'a> {
+    fn new(warning: &'a Warning) -> Self {
+        let vtec = warning.vtec.as_ref();
+        let points: Vec<_> = warning
+            .polygon
+            .iter()
+            .map(|(lat, lon)| format!("{lon} {lat}"))
+            .collect();
+
+        Self {
+            issued: warning.issued,
+            expires: vtec.and_then(|vtec| vtec.end),
+            office: vtec.map(|vtec| vtec.office.as_str()),
+            etn: vtec.map(|vtec| vtec.etn),
+            action: vtec.map(|vtec| vtec.action.as_str()),
+            severity: warning.severity,
+            observed: warning.observed,
+            waterspout: warning.waterspout,
+            verified: warning.verified,
+            max_hail_size: warning.max_hail_size,
+            max_wind_gust: warning.max_wind_gust,
+            source: warning.source.as_deref(),
+            polygon: format!("POLYGON(({}))", points.join(", ")),
+        }
+    }
+}
+
+/// A response body being built in the format a query asks for.
+pub struct Output<'a> {
+    query: &'a Query,
+    /// The config, with the colors of the query's palette if it has one.
+    config: Cow<'a, Config>,
+    body: Body,
+    /// How many warnings of each severity were added, for the legend.
+    counts: [usize; Severity::ALL.len()],
+    /// The northernmost latitude and westernmost longitude of the warnings added.
+    corner: Option<(f32, f32)>,
+    /// The length of a placefile's header, which the legend goes right after.
+    header: usize,
+    /// The range of the last warning added to a placefile, when the query has several.
+    group: Option<&'a Range>,
+}
+
+/// Packs finished files into a zip, named by the day or month they cover.
+fn zip_files(files: BTreeMap<String, Body>, inner: Inner) -> Vec<u8> {
+    use zip::write::{SimpleFileOptions, ZipWriter};
+
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    for (key, file) in files {
+        let name = format!("warnings_{key}.{}", inner.extension());
+        zip.start_file(name, SimpleFileOptions::default()).unwrap();
+        zip.write_all(&file.finish()).unwrap();
+    }
+
+    zip.finish().unwrap().into_inner()
+}
+
+impl<'a> Output<'a> {
+    pub fn new(query: &'a Query, config: &'a Config) -> Self {
+        let config = match (query.palette, query.alpha) {
+            (None, None) => Cow::Borrowed(config),
+            (palette, alpha) => {
+                let mut colors = match palette {
+                    Some(palette) => config.colors.with_palette(palette),
+                    None => config.colors.clone(),
+                };
+                if let Some(alpha) = alpha {
+                    colors = colors.with_fill_alpha(alpha);
+                }
+                Cow::Owned(Config {
+                    colors,
+                    ..config.clone()
+                })
+            }
+        };
+
+        let body = match query.format {
+            Format::Zip(Split::Day, inner) => Body::Zip(BTreeMap::new(), "%F", inner),
+            Format::Zip(Split::Month, inner) => Body::Zip(BTreeMap::new(), "%Y-%m", inner),
+            format => Body::file(format.files(), query, &config),
+        };
+        let header = match &body {
+            Body::Placefile(writer) => writer.len(),
+            _ => 0,
+        };
+
+        Self {
+            query,
+            config,
+            body,
+            counts: [0; Severity::ALL.len()],
+            corner: None,
+            header,
+            group: None,
+        }
+    }
+
+    /// Counts a warning and extends the corner of the warnings to its polygon, for the legend.
+    fn tally(&mut self, warning: &Warning) {
+        let index = Severity::ALL.iter().position(|&s| s == warning.severity).unwrap();
+        self.counts[index] += 1;
+
+        for &(lat, lon) in &warning.polygon {
+            let (north, west) = self.corner.get_or_insert((lat, lon));
+            *north = north.max(lat);
+            *west = west.min(lon);
+        }
+    }
+
+    /// Heads the warnings a placefile gets from each range with a comment naming it, when the
+    /// query has several.
+    fn group(&mut self, time: DateTime<Utc>) {
+        let range = self.query.range_of(time);
+        if range.is_none() || range == self.group {
+            return;
+        }
+        if self.query.format.files() != Inner::Placefile {
+            return;
+        }
+        if let Body::Placefile(writer) = self.body.file_for(time, self.query, &self.config) {
+            writeln!(writer, "; {}\n", range.unwrap().label()).unwrap();
+            self.group = range;
+        }
+    }
+
+    /// Adds a warning's polygon.
+    pub fn warning(&mut self, warning: &Warning) {
+        self.tally(warning);
+        self.group(warning.issued);
+        let (query, config) = (self.query, &*self.config);
+        match self.body.file_for(warning.issued, query, config) {
+            Body::GeoJson(writer, first) => {
+                // Web maps have no colors of their own, so they get the palette's.
+                let style = query.palette.map(|_| render::style(warning, &config.colors));
+                let range = query.range_of(warning.issued).map(Range::label);
+                geojson::feature(writer, warning, style, range, query.crs, *first);
+                *first = false;
+            }
+            Body::Csv(writer) => writer.serialize(Row::new(warning)).unwrap(),
+            Body::Placefile(writer) => {
+                if query.time_ranges() {
+                    // Warnings without an expiration are shown for as long as they usually last.
+                    let expires = warning.vtec.as_ref().and_then(|vtec| vtec.end);
+                    let expires = expires.unwrap_or(warning.issued + Duration::minutes(45));
+                    render::time_range(writer, warning.issued, expires);
+                }
+                if query.mode == Mode::Fill {
+                    render::fill(writer, &warning.polygon, render::style(warning, &config.colors));
+                }
+                render::warning(writer, warning, config, query.timefmt())
+            }
+            Body::Zip(..) => unreachable!("zips hold files, not zips"),
+        }
+    }
+
+    /// Adds an event's storm track, if it has one.
+    pub fn track(&mut self, event: &Event) {
+        let track = event.track();
+        if track.len() < 2 {
+            return;
+        }
+        for warning in &event.warnings {
+            self.tally(warning);
+        }
+        self.group(event.first().issued);
+
+        let (query, config) = (self.query, &*self.config);
+        match self.body.file_for(event.first().issued, query, config) {
+            Body::GeoJson(writer, first) => {
+                let style = query.palette.map(|_| render::style(event.latest(), &config.colors));
+                geojson::track(writer, event, &track, style, query.crs, *first);
+                *first = false;
+            }
+            // Tracks aren't rows, and queries can't ask for both.
+            Body::Csv(_) => (),
+            Body::Placefile(writer) => {
+                if query.time_ranges() {
+                    let ended = event.ended().unwrap_or(event.latest().issued);
+                    render::time_range(writer, event.first().issued, ended);
+                }
+                render::track(writer, event, &track, config, query.timefmt())
+            }
+            Body::Zip(..) => unreachable!("zips hold files, not zips"),
+        }
+    }
+
+    /// Adds a comment summarizing the skipped products to a placefile.
+    pub fn skips(&mut self, skips: &Skips) {
+        if let Body::Placefile(writer) = &mut self.body {
+            render::skips(writer, skips);
+        }
+    }
+
+    /// Finishes the body.
+    pub fn finish(self) -> Vec<u8> {
+        match self.body {
+            Body::Placefile(mut writer) => {
+                // The legend needs every warning counted, so it is rendered last but goes first.
+                if self.query.legend {
+                    let counts: Vec<_> = Severity::ALL.into_iter().zip(self.counts).collect();
+                    let mut legend = Vec::new();
+                    render::legend(&mut legend, self.query, &counts, self.corner);
+                    writer.splice(self.header..self.header, legend);
+                }
+                writer
+            }
+            body => body.finish(),
+        }
+    }
+}
@@ -0,0 +1,398 @@
+use chrono::{DateTime, NaiveDateTime, Utc};
+use serde::Serialize;
+
+/// The WMO heading, such as `WFUS54 KOUN 012245`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Wmo {
+    pub id: String,
+    pub office: String,
+    /// The day, hour and minute the product was sent, as `ddhhmm`.
+    pub time: String,
+}
+
+impl Wmo {
+    fn parse(line: &str) -> Option<Self> {
+        let mut parts = line.split_whitespace();
+        let (id, office, time) = (parts.next()?, parts.next()?, parts.next()?);
+
+        // Checked as bytes, since garbled text can have multi-byte characters anywhere.
+        let valid = id.len() == 6
+            && id.as_bytes()[..4].iter().all(u8::is_ascii_uppercase)
+            && id.as_bytes()[4..].iter().all(u8::is_ascii_digit)
+            && office.len() == 4
+            && office.chars().all(|c| c.is_ascii_alphanumeric())
+            && time.len() == 6
+            && time.chars().all(|c| c.is_ascii_digit());
+
+        valid.then(|| Self {
+            id: id.to_string(),
+            office: office.to_string(),
+            time: time.to_string(),
+        })
+    }
+}
+
+/// A VTEC string, such as `/O.NEW.KOUN.TO.W.0012.220501T2245Z-220501T2330Z/`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Vtec {
+    pub class: char,
+    /// The action code, such as `NEW` or `CAN`.
+    pub action: String,
+    /// The issuing office, such as `KOUN`.
+    pub office: String,
+    /// The phenomenon code, `TO` for tornadoes.
+    pub phenomenon: String,
+    /// The significance code, `W` for warnings.
+    pub significance: char,
+    /// The event tracking number.
+    pub etn: u32,
+    /// When the event begins. Missing once an event is in progress.
+    pub begin: Option<DateTime<Utc>>,
+    /// When the event ends.
+    pub end: Option<DateTime<Utc>>,
+}
+
+/// Parses a VTEC time, which is all zeroes when it doesn't apply.
+fn vtec_time(s: &str) -> Option<DateTime<Utc>> {
+    NaiveDateTime::parse_from_str(s, "%y%m%dT%H%MZ")
+        .ok()
+        .map(|time| time.and_utc())
+}
+
+impl Vtec {
+    pub fn parse(line: &str) -> Option<Self> {
+        let inner = line.trim().strip_prefix('/')?.strip_suffix('/')?;
+        let parts: Vec<_> = inner.split('.').collect();
+        let [class, action, office, phenomenon, significance, etn, times] = parts[..] else {
+            return None;
+        };
+        let (begin, end) = times.split_once('-')?;
+
+        Some(Self {
+            class: class.chars().next()?,
+            action: action.to_string(),
+            office: office.to_string(),
+            phenomenon: phenomenon.to_string(),
+            significance: significance.chars().next()?,
+            etn: etn.parse().ok()?,
+            begin: vtec_time(begin),
+            end: vtec_time(end),
+        })
+    }
+
+    /// The VTEC code without its times, such as `O.NEW.KOUN.TO.W.0012`.
+    pub fn code(&self) -> String {
+        format!(
+            "{}.{}.{}.{}.{}.{:04}",
+            self.class, self.action, self.office, self.phenomenon, self.significance, self.etn
+        )
+    }
+}
+
+/// The storm's position and motion, from the `TIME...MOT...LOC` line.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Motion {
+    /// The time of the observation, as `hhmmZ`.
+    pub time: String,
+    /// The direction the storm is moving from, in degrees.
+    pub direction: u32,
+    /// The storm's speed, in knots.
+    pub speed: u32,
+    /// The storm's location as (lat, lon) pairs, more than one for a line of storms.
+    pub locations: Vec<(f32, f32)>,
+}
+
+impl Motion {
+    fn parse(rest: &str) -> Option<Self> {
+        let mut parts = rest.split_whitespace();
+        let time = parts.next()?.to_string();
+        let direction = parts.next()?.strip_suffix("DEG")?.parse().ok()?;
+        let speed = parts.next()?.strip_suffix("KT")?.parse().ok()?;
+        let locations = coordinates(&parts.collect::<Vec<_>>().join(" "))?;
+
+        Some(Self {
+            time,
+            direction,
+            speed,
+            locations,
+        })
+    }
+}
+
+/// Parses pairs of `LLLL OOOOO` coordinates in hundredths of a degree, with west longitudes.
+fn coordinates(s: &str) -> Option<Vec<(f32, f32)>> {
+    let values = s
+        .split_whitespace()
+        .map(|v| v.parse::<f32>().map(|v| v / 100.).ok())
+        .collect::<Option<Vec<_>>>()?;
+
+    Some(values.chunks_exact(2).map(|co| (co[0], -co[1])).collect())
+}
+
+/// Splits a line into a `KEY...value` tag, such as `MAX HAIL SIZE...2.50 IN`.
+fn tag(line: &str) -> Option<(&str, &str)> {
+    let (key, value) = line.trim().split_once("...")?;
+    let valid = key.starts_with(|c: char| c.is_ascii_uppercase())
+        && key.chars().all(|c| c.is_ascii_uppercase() || c == ' ');
+
+    valid.then(|| (key, value.trim()))
+}
+
+/// Tests if a line only continues a list of coordinates.
+fn is_coordinates(line: &str) -> bool {
+    let line = line.trim();
+    !line.is_empty() && line.chars().all(|c| c.is_ascii_digit() || c == ' ')
+}
+
+/// A NWS text product segment, split into the parts this program cares about.
+#[derive(Debug, Clone, Default)]
+pub struct Product<'a> {
+    pub wmo: Option<Wmo>,
+    /// The AWIPS identifier, such as `TOROUN`.
+    pub awips: Option<&'a str>,
+    /// The UGC codes of the affected counties or zones, such as `OKC017`.
+    pub ugc: Vec<String>,
+    /// When the UGC block expires, as `ddhhmm`.
+    pub ugc_expires: Option<String>,
+    pub vtec: Vec<Vtec>,
+    /// The polygon from the `LAT...LON` line as (lat, lon) pairs, not closed.
+    pub polygon: Vec<(f32, f32)>,
+    pub motion: Option<Motion>,
+    /// Every `KEY...value` tag, with the value's continuation lines joined.
+    pub tags: Vec<(&'a str, String)>,
+}
+
+impl<'a> Product<'a> {
+    /// Parses a product segment in one pass over its lines.
+    pub fn parse(text: &'a str) -> Self {
+        let lines: Vec<&str> = text
+            .lines()
+            .map(|line| line.trim_end_matches('\r'))
+            .collect();
+        let mut product = Product::default();
+        let mut i = 0;
+
+        while i < lines.len() {
+            let line = lines[i].trim();
+            i += 1;
+
+            if product.wmo.is_none() {
+                if let Some(wmo) = Wmo::parse(line) {
+                    product.wmo = Some(wmo);
+                    // The AWIPS id is on the line after the WMO heading.
+                    product.awips = lines.get(i).map(|line| line.trim()).filter(|id| {
+                        (4..=6).contains(&id.len()) && id.chars().all(|c| c.is_ascii_alphanumeric())
+                    });
+                    continue;
+                }
+            }
+
+            if let Some(vtec) = Vtec::parse(line) {
+                product.vtec.push(vtec);
+            } else if let Some(rest) = line.strip_prefix("LAT...LON") {
+                let mut coords = rest.to_string();
+                while i < lines.len() && is_coordinates(lines[i]) {
+                    coords.push(' ');
+                    coords.push_str(lines[i]);
+                    i += 1;
+                }
+                product.polygon = coordinates(&coords).unwrap_or_default();
+            } else if let Some(rest) = line.strip_prefix("TIME...MOT...LOC") {
+                let mut motion = rest.to_string();
+                while i < lines.len() && is_coordinates(lines[i]) {
+                    motion.push(' ');
+                    motion.push_str(lines[i]);
+                    i += 1;
+                }
+                product.motion = Motion::parse(&motion);
+            } else if product.ugc.is_empty() && is_ugc(line) {
+                let mut ugc = line.to_string();
+                // A garbled block might never end, so it stops at the first line that can't
+                // continue it rather than taking the rest of the product.
+                while !ends_ugc(&ugc) && i < lines.len() && continues_ugc(lines[i]) {
+                    ugc.push_str(lines[i].trim());
+                    i += 1;
+                }
+                (product.ugc, product.ugc_expires) = parse_ugc(&ugc);
+            } else if let Some((key, value)) = tag(line) {
+                let mut value = value.to_string();
+                // Values can wrap onto the next lines until a blank line or another tag.
+                while i < lines.len() && !lines[i].trim().is_empty() && tag(lines[i]).is_none() {
+                    value.push(' ');
+                    value.push_str(lines[i].trim());
+                    i += 1;
+                }
+                product.tags.push((key, value));
+            }
+        }
+
+        product
+    }
+
+    /// Returns the value of the first tag with one of the given keys.
+    pub fn tag(&self, keys: &[&str]) -> Option<&str> {
+        self.tags
+            .iter()
+            .find(|(key, _)| keys.contains(key))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Tests if a line starts a UGC block, such as `OKC017-027-012330-`.
+fn is_ugc(line: &str) -> bool {
+    let bytes = line.as_bytes();
+    bytes.len() >= 7
+        && bytes[..2].iter().all(u8::is_ascii_uppercase)
+        && matches!(bytes[2], b'C' | b'Z')
+        && bytes[3..6].iter().all(u8::is_ascii_digit)
+        && matches!(bytes[6], b'-' | b'>')
+}
+
+/// Tests if a line can continue a UGC block, such as `027>031-202145-`.
+fn continues_ugc(line: &str) -> bool {
+    let line = line.trim();
+    !line.is_empty() && line.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'>')
+}
+
+/// Tests if a UGC block is complete, which is when it ends with the expiration time.
+fn ends_ugc(ugc: &str) -> bool {
+    ugc.strip_suffix('-')
+        .and_then(|ugc| ugc.rsplit('-').next())
+        .is_some_and(|last| last.len() == 6 && last.chars().all(|c| c.is_ascii_digit()))
+}
+
+/// Expands a UGC block into its codes and expiration time.
+fn parse_ugc(ugc: &str) -> (Vec<String>, Option<String>) {
+    let mut codes = Vec::new();
+    let mut expires = None;
+    let mut prefix = "";
+
+    for part in ugc.split('-').filter(|part| !part.is_empty()) {
+        if part.len() == 6 && part.chars().all(|c| c.is_ascii_digit()) {
+            expires = Some(part.to_string());
+            continue;
+        }
+
+        let (first, last) = part.split_once('>').unwrap_or((part, part));
+        // Parts are sliced with `get`, which fails instead of panicking inside a garbled character.
+        let number = |s: &'_ str| -> Option<u32> {
+            let digits = if s.len() == 6 { s.get(3..)? } else { s };
+            digits.parse().ok()
+        };
+
+        if let Some(state) = first.get(..3).filter(|_| first.len() == 6) {
+            prefix = state;
+        }
+        if let (Some(first), Some(last)) = (number(first), number(last)) {
+            codes.extend((first..=last).map(|n| format!("{prefix}{n:03}")));
+        }
+    }
+
+    (codes, expires)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn wmo_headings() {
+        let wmo = Wmo::parse("WFUS54 KOUN 202101").unwrap();
+        assert_eq!((wmo.id.as_str(), wmo.office.as_str()), ("WFUS54", "KOUN"));
+        assert_eq!(wmo.time, "202101");
+
+        assert_eq!(Wmo::parse("Tornado Warning for"), None);
+        assert_eq!(Wmo::parse("WFUS54 KOUN 2021"), None);
+        // Multi-byte characters where the digits or letters should be.
+        assert_eq!(Wmo::parse("WFUS5é KOUN 202101"), None);
+        assert_eq!(Wmo::parse("WFUé4 KOUN 202101"), None);
+        assert_eq!(Wmo::parse("WFUS54 KOUN 20210é"), None);
+    }
+
+    #[test]
+    fn vtec_strings() {
+        let vtec = Vtec::parse("/O.NEW.KOUN.TO.W.0020.240520T2101Z-240520T2145Z/").unwrap();
+        assert_eq!(vtec.code(), "O.NEW.KOUN.TO.W.0020");
+        assert_eq!(vtec.etn, 20);
+        assert_eq!(vtec.begin, Utc.with_ymd_and_hms(2024, 5, 20, 21, 1, 0).single());
+        assert_eq!(vtec.end, Utc.with_ymd_and_hms(2024, 5, 20, 21, 45, 0).single());
+
+        // Events in progress have no begin time.
+        let vtec = Vtec::parse("/O.CON.KOUN.TO.W.0020.000000T0000Z-240520T2145Z/").unwrap();
+        assert_eq!((vtec.action.as_str(), vtec.begin), ("CON", None));
+
+        assert_eq!(Vtec::parse("/O.NEW.KOUN.TO.W.00é0.240520T2101Z-240520T2145Z/"), None);
+        assert_eq!(Vtec::parse("/O.NEW.KOUN.TO.W.0020/"), None);
+        assert_eq!(Vtec::parse("O.NEW.KOUN.TO.W.0020.240520T2101Z-240520T2145Z"), None);
+    }
+
+    #[test]
+    fn ugc_blocks() {
+        let (codes, expires) = parse_ugc("OKC017-027>031-202145-");
+        assert_eq!(codes, ["OKC017", "OKC027", "OKC028", "OKC029", "OKC030", "OKC031"]);
+        assert_eq!(expires.as_deref(), Some("202145"));
+
+        let (codes, _) = parse_ugc("OKC017-TXC485-487-202145-");
+        assert_eq!(codes, ["OKC017", "TXC485", "TXC487"]);
+
+        assert!(is_ugc("OKC017-202145-"));
+        assert!(!is_ugc("OKé17-202345-"));
+        assert!(ends_ugc("OKC017-202145-"));
+        assert!(!ends_ugc("OKC017-027-"));
+    }
+
+    #[test]
+    fn garbled_ugc_blocks_dont_panic() {
+        for ugc in ["OKé17-202345-", "OKC01é-202345-", "éééééé-", "OKC017>é-", "-->--", ""] {
+            parse_ugc(ugc);
+        }
+        assert!(parse_ugc("OKé17-202345-").0.is_empty());
+    }
+
+    #[test]
+    fn product_segments() {
+        let text = "WFUS54 KOUN 202101\nTOROUN\nOKC017-\n027-202145-\n\
+                    /O.NEW.KOUN.TO.W.0020.240520T2101Z-240520T2145Z/\n\n\
+                    IMPACT...Flying debris\n         may be deadly.\n\n\
+                    LAT...LON 3526 9768 3537 9768 3538 9742\n      3524 9750\n\
+                    TIME...MOT...LOC 2101Z 270DEG 17KT 3530 9762\n\n\
+                    MAX HAIL SIZE...1.75 IN\n";
+        let product = Product::parse(text);
+
+        assert_eq!(product.wmo.as_ref().unwrap().office, "KOUN");
+        assert_eq!(product.awips, Some("TOROUN"));
+        assert_eq!(product.ugc, ["OKC017", "OKC027"]);
+        assert_eq!(product.ugc_expires.as_deref(), Some("202145"));
+        assert_eq!(product.vtec.len(), 1);
+        assert_eq!(
+            product.polygon,
+            [(35.26, -97.68), (35.37, -97.68), (35.38, -97.42), (35.24, -97.5)]
+        );
+
+        let motion = product.motion.as_ref().unwrap();
+        assert_eq!((motion.direction, motion.speed), (270, 17));
+        assert_eq!(motion.locations, [(35.3, -97.62)]);
+
+        assert_eq!(product.tag(&["IMPACT"]), Some("Flying debris may be deadly."));
+        assert_eq!(product.tag(&["MAX HAIL SIZE", "HAIL"]), Some("1.75 IN"));
+    }
+
+    #[test]
+    fn unterminated_ugc_blocks_stop_at_the_vtec() {
+        let text = "OKC087-\n/O.NEW.KOUN.TO.W.0022.240520T2300Z-240520T2330Z/\n\n\
+                    LAT...LON 3510 9740 3520 9740 3520 9730\n";
+        let product = Product::parse(text);
+
+        assert_eq!(product.ugc, ["OKC087"]);
+        assert_eq!(product.ugc_expires, None);
+        assert_eq!(product.vtec.len(), 1);
+        assert_eq!(product.polygon.len(), 3);
+    }
+
+    #[test]
+    fn garbled_polygons_are_dropped() {
+        let product = Product::parse("LAT...LON 3545 98O8 3561 9785 3552 9770\n");
+        assert!(product.polygon.is_empty());
+    }
+}
@@ -0,0 +1,1083 @@
+use crate::geo;
+use crate::index;
+use crate::warning::Warning;
+use crate::{parse_params, parse_times, HttpError, HttpResult};
+use chrono::{DateTime, NaiveDate, NaiveTime, Utc};
+use lazy_static::lazy_static;
+use regex::{Regex, RegexBuilder};
+use std::collections::BTreeMap;
+
+/// The longest pattern accepted for the `regex` parameter.
+const REGEX_MAX_LEN: usize = 256;
+/// The first year of VTEC event numbers, before which `/event` has nothing to find.
+const FIRST_EVENT_YEAR: i32 = 2005;
+
+/// How many hours into the next year an event issued late on Dec 31 is followed.
+const EVENT_OVERRUN_HOURS: i64 = 6;
+
+/// The most memory a compiled `regex` parameter may use.
+const REGEX_SIZE_LIMIT: usize = 1 << 20;
+/// The longest format accepted for the `timefmt` parameter.
+const TIMEFMT_MAX_LEN: usize = 64;
+/// How times are written in labels unless the query asks otherwise.
+pub const DEFAULT_TIMEFMT: &str = "%c";
+
+/// What kind of value a parameter takes.
+#[derive(Debug, Clone, Copy)]
+pub enum Kind {
+    Text,
+    /// A day as `YYYY-MM-DD` or a keyword such as `today`, an ISO-8601 datetime such as
+    /// `YYYY-MM-DDTHH:MMZ`, or a Unix timestamp.
+    Time,
+    /// Two times separated by a slash, such as `2011-04-27/2011-04-28`.
+    Range,
+    Number,
+    /// One of a fixed set of values.
+    Choice(&'static [&'static str]),
+}
+
+/// A flag, which is on with `1` or `true` and off with `0` or `false`.
+const FLAG: Kind = Kind::Choice(&["0", "1", "true", "false"]);
+
+/// A query parameter, described for error messages and `/openapi.json`.
+#[derive(Debug, Clone, Copy)]
+pub struct Param {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub kind: Kind,
+    pub default: Option<&'static str>,
+    pub required: bool,
+    /// Whether the parameter can be given more than once.
+    pub repeatable: bool,
+}
+
+impl Param {
+    pub const fn new(name: &'static str, kind: Kind, description: &'static str) -> Self {
+        Self {
+            name,
+            description,
+            kind,
+            default: None,
+            required: false,
+            repeatable: false,
+        }
+    }
+
+    pub const fn default(self, default: &'static str) -> Self {
+        Self {
+            default: Some(default),
+            ..self
+        }
+    }
+
+    pub const fn required(self) -> Self {
+        Self {
+            required: true,
+            ..self
+        }
+    }
+
+    pub const fn repeatable(self) -> Self {
+        Self {
+            repeatable: true,
+            ..self
+        }
+    }
+}
+
+/// The parameters accepted by a warnings request. Any others are rejected.
+pub const PARAMS: &[Param] = &[
+    Param::new(
+        "start",
+        Kind::Time,
+        "The first day or time, such as 2022-05-01, yesterday, 2022-05-01T18:00Z or 1651428000. \
+         Required unless range is given.",
+    ),
+    Param::new(
+        "end",
+        Kind::Time,
+        "The last day or time, such as 2022-05-31, today, 2022-05-02T06:00Z or 1651471200. \
+         Required unless range is given.",
+    ),
+    Param::new(
+        "range",
+        Kind::Range,
+        "A start and end separated by a slash, such as 2011-04-27/2011-04-28, instead of start \
+         and end. Repeat it for several ranges.",
+    )
+    .repeatable(),
+    Param::new("tz", Kind::Text, "The UTC offset days are in, such as -05:00 or CDT.")
+        .default("UTC"),
+    Param::new(
+        "format",
+        Kind::Choice(&["placefile", "zip", "geojson", "csv"]),
+        "The response format.",
+    )
+    .default("placefile"),
+    Param::new("split", Kind::Choice(&["day", "month"]), "How a zip is split into files.")
+        .default("day"),
+    Param::new(
+        "inner",
+        Kind::Choice(&["placefile", "geojson", "csv"]),
+        "The format of the files in a zip.",
+    )
+    .default("placefile"),
+    Param::new("crs", Kind::Choice(&["EPSG:4326", "EPSG:3857"]), "The CRS of GeoJSON.")
+        .default("EPSG:4326"),
+    Param::new("contains", Kind::Text, "Only warnings containing this phrase, ignoring case."),
+    Param::new("regex", Kind::Text, "Only warnings matching this regex, up to 256 characters."),
+    Param::new("layer", Kind::Choice(&["observed", "radar"]), "Only this layer of warnings."),
+    Param::new("source", Kind::Text, "Only warnings whose source contains this, ignoring case."),
+    Param::new("wfo", Kind::Text, "Only warnings from these offices, such as OUN,TSA."),
+    Param::new("exclude_wfo", Kind::Text, "Leaves out warnings from these offices, like JAN,BMX."),
+    Param::new("min_duration", Kind::Text, "Only warnings valid for at least this long, like 30m."),
+    Param::new("max_duration", Kind::Text, "Only warnings valid for at most this long, like 1h."),
+    Param::new("lat", Kind::Number, "The latitude of a point to keep warnings near, with radius."),
+    Param::new("lon", Kind::Number, "The longitude of a point to keep warnings near, with radius."),
+    Param::new("radius", Kind::Text, "How near the point warnings must come, like 50mi or 80km."),
+    Param::new(
+        "per_event",
+        Kind::Choice(&["all", "first", "latest"]),
+        "Which products of each event to keep.",
+    )
+    .default("all"),
+    Param::new(
+        "mode",
+        Kind::Choice(&["outline", "fill", "track"]),
+        "What to draw. Tracks can't be CSV.",
+    )
+    .default("outline"),
+    Param::new("simplify", Kind::Number, "Simplifies polygons with this tolerance in degrees."),
+    Param::new("verified", FLAG, "1 keeps only warnings with tornado reports.")
+        .default("0"),
+    Param::new("palette", Kind::Choice(&["cvd", "dark", "light"]), "Preset colors to draw with."),
+    Param::new("skips", FLAG, "1 adds a comment counting skipped products.")
+        .default("0"),
+    Param::new("timefmt", Kind::Text, "A strftime format for times in labels, up to 64 characters.")
+        .default(DEFAULT_TIMEFMT),
+    Param::new("alpha", Kind::Number, "The fill opacity with mode=fill, from 0 to 255."),
+    Param::new(
+        "legend",
+        FLAG,
+        "1 adds text saying what a placefile shows and when it was made.",
+    )
+    .default("0"),
+    Param::new(
+        "dialect",
+        Kind::Choice(&["grlevel3", "gr2analyst", "grearth"]),
+        "The application a placefile is for, which decides the directives used.",
+    ),
+    Param::new("count", FLAG, "1 returns only the number of warnings.")
+        .default("0"),
+];
+
+/// The names of the warnings parameters `/tiles/{z}/{x}/{y}.mvt` accepts, which are the ones that
+/// pick warnings rather than shape the response.
+const TILE_PARAM_NAMES: &[&str] = &[
+    "start",
+    "end",
+    "range",
+    "tz",
+    "contains",
+    "regex",
+    "layer",
+    "source",
+    "wfo",
+    "exclude_wfo",
+    "min_duration",
+    "max_duration",
+    "lat",
+    "lon",
+    "radius",
+    "per_event",
+    "verified",
+];
+
+lazy_static! {
+    /// The parameters accepted by `/tiles/{z}/{x}/{y}.mvt`.
+    pub static ref TILE_PARAMS: Vec<Param> = PARAMS
+        .iter()
+        .filter(|param| TILE_PARAM_NAMES.contains(&param.name))
+        .copied()
+        .collect();
+}
+
+/// The parameters accepted by `/api/search`.
+pub const SEARCH_PARAMS: &[Param] = &[
+    Param::new("vtec", Kind::Text, "A full or partial VTEC code, such as KOUN.TO.W.0045.")
+        .required(),
+    Param::new("start", Kind::Time, "The first day of a range to also search."),
+    Param::new("end", Kind::Time, "The last day of a range to also search."),
+    Param::new("tz", Kind::Text, "The UTC offset days are in, such as -05:00 or CDT.")
+        .default("UTC"),
+];
+
+/// The parameters accepted by `/product/{id}`.
+pub const PRODUCT_PARAMS: &[Param] = &[Param::new(
+    "format",
+    Kind::Choice(&["placefile", "text"]),
+    "A placefile of the product's polygons, or its raw text.",
+)
+.default("placefile")];
+
+/// Replaces the `start`, `end` and `range` parameters with the times they resolved to, for cache
+/// keys.
+///
+/// Otherwise a response cached for `today` would still be served after the day is over.
+pub fn resolve_times(
+    mut params: BTreeMap<String, String>,
+    query: &Query,
+) -> BTreeMap<String, String> {
+    let resolve = |time: Option<DateTime<Utc>>, day: DateTime<Utc>| match time {
+        Some(time) => time.to_rfc3339(),
+        None => day.format("%F").to_string(),
+    };
+    for name in ["tz", "start", "end", "range"] {
+        params.remove(name);
+    }
+    match &query.ranges[..] {
+        [range] => {
+            params.insert("start".to_string(), resolve(range.after, range.start));
+            params.insert("end".to_string(), resolve(range.before, range.end));
+        }
+        ranges => {
+            let ranges: Vec<_> = ranges
+                .iter()
+                .map(|range| {
+                    let (start, end) = (range.after, range.before);
+                    format!("{}/{}", resolve(start, range.start), resolve(end, range.end))
+                })
+                .collect();
+            params.insert("range".to_string(), ranges.join(","));
+        }
+    }
+
+    params
+}
+
+/// Whether a parameter of any endpoint can be given more than once.
+pub fn is_repeatable(name: &str) -> bool {
+    PARAMS
+        .iter()
+        .chain(TILE_PARAMS.iter())
+        .any(|param| param.name == name && param.repeatable)
+}
+
+/// Rejects parameters that aren't in the accepted list, so typos don't go unnoticed.
+pub fn check_params(params: &BTreeMap<String, String>, accepted: &[Param]) -> HttpResult<()> {
+    match params
+        .keys()
+        .find(|name| !accepted.iter().any(|param| param.name == name.as_str()))
+    {
+        Some(name) => Err(HttpError::Invalid(format!("unknown parameter {name}"))),
+        None => Ok(()),
+    }
+}
+
+/// Describes the request to a path, for replying to one that is invalid.
+pub fn usage(path: &str) -> String {
+    let (params, usage, example) = if path == "/api/search" {
+        (
+            SEARCH_PARAMS,
+            "/api/search?vtec=CODE[&name=value...]",
+            "/api/search?vtec=KOUN.TO.W.0045",
+        )
+    } else if path.starts_with("/tiles/") {
+        (
+            TILE_PARAMS.as_slice(),
+            "/tiles/{z}/{x}/{y}.mvt?start=YYYY-MM-DD&end=YYYY-MM-DD[&name=value...]",
+            "/tiles/6/14/25.mvt?start=2022-05-01&end=2022-05-31",
+        )
+    } else if path.starts_with("/event/") {
+        (
+            &[][..],
+            "/event/{year}/{wfo}/{etn}.txt, or .json for the event as JSON",
+            "/event/2013/OUN/45.txt",
+        )
+    } else if path.starts_with("/product/") {
+        (
+            PRODUCT_PARAMS,
+            "/product/{id}[?name=value]",
+            "/product/202205012245-KOUN-WFUS54-TOROUN?format=text",
+        )
+    } else {
+        (
+            PARAMS,
+            "/warnings.txt?start=YYYY-MM-DD&end=YYYY-MM-DD[&name=value...]",
+            "/warnings.txt?start=2022-05-01&end=2022-05-31&layer=observed",
+        )
+    };
+
+    let mut usage = format!("Usage: {usage}\n");
+    if !params.is_empty() {
+        usage += "\nParameters:\n";
+    }
+    for param in params {
+        usage += &format!("  {:<11} {}", param.name, param.description);
+        if let Kind::Choice(values) = param.kind {
+            usage += &format!(" One of {}.", values.join(", "));
+        }
+        if let Some(default) = param.default {
+            usage += &format!(" Defaults to {default}.");
+        }
+        if param.required {
+            usage += " Required.";
+        }
+        usage += "\n";
+    }
+
+    usage + &format!("\nExample: http://localhost:8888{example}\n")
+}
+
+/// Parses an optional parameter, saying which one is malformed if it can't be parsed.
+fn param<T>(
+    params: &BTreeMap<String, String>,
+    name: &str,
+    parse: impl FnOnce(&str) -> HttpResult<T>,
+) -> HttpResult<Option<T>> {
+    params
+        .get(name)
+        .map(|value| {
+            parse(value).map_err(|_| HttpError::Invalid(format!("invalid {name}: {value}")))
+        })
+        .transpose()
+}
+
+/// Compiles a user-supplied regex, rejecting ones that are too long or too complex.
+///
+/// There is no need for a match timeout, since the regex crate always matches in linear time.
+fn user_regex(pattern: &str) -> HttpResult<Regex> {
+    if pattern.len() > REGEX_MAX_LEN {
+        return Err(HttpError::BadRequest);
+    }
+
+    RegexBuilder::new(pattern)
+        .size_limit(REGEX_SIZE_LIMIT)
+        .dfa_size_limit(REGEX_SIZE_LIMIT)
+        .build()
+        .map_err(|_| HttpError::BadRequest)
+}
+
+/// A subset of warnings that can be requested on its own, to toggle it separately.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Layer {
+    Observed,
+    Radar,
+}
+
+impl Layer {
+    fn parse(s: &str) -> HttpResult<Self> {
+        match s {
+            "observed" => Ok(Layer::Observed),
+            "radar" => Ok(Layer::Radar),
+            _ => Err(HttpError::BadRequest),
+        }
+    }
+
+    /// The name of the layer, for placefile titles.
+    pub fn title(self) -> &'static str {
+        match self {
+            Layer::Observed => "observed",
+            Layer::Radar => "radar indicated",
+        }
+    }
+}
+
+/// How many polygons of each event a query wants.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PerEvent {
+    /// Every product, including each update.
+    #[default]
+    All,
+    /// Only the product that started the event.
+    First,
+    /// Only the most recent product of the event.
+    Latest,
+}
+
+impl PerEvent {
+    fn parse(s: &str) -> HttpResult<Self> {
+        match s {
+            "all" => Ok(PerEvent::All),
+            "first" => Ok(PerEvent::First),
+            "latest" => Ok(PerEvent::Latest),
+            _ => Err(HttpError::BadRequest),
+        }
+    }
+}
+
+/// What is drawn for the warnings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Mode {
+    /// Each warning's polygon.
+    #[default]
+    Outline,
+    /// Each warning's polygon, filled in with its color.
+    Fill,
+    /// A line through the centroids of each event's successive polygons.
+    Track,
+}
+
+impl Mode {
+    fn parse(s: &str) -> HttpResult<Self> {
+        match s {
+            "outline" => Ok(Mode::Outline),
+            "fill" => Ok(Mode::Fill),
+            "track" => Ok(Mode::Track),
+            _ => Err(HttpError::BadRequest),
+        }
+    }
+}
+
+/// The application a placefile is made for, which decides the directives it can use.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Dialect {
+    GrLevel3,
+    Gr2Analyst,
+    GrEarth,
+}
+
+impl Dialect {
+    fn parse(s: &str) -> HttpResult<Self> {
+        match s {
+            "grlevel3" => Ok(Dialect::GrLevel3),
+            "gr2analyst" => Ok(Dialect::Gr2Analyst),
+            "grearth" => Ok(Dialect::GrEarth),
+            _ => Err(HttpError::BadRequest),
+        }
+    }
+
+    /// Whether the application supports `TimeRange:`, showing each warning only while it was in
+    /// effect at the radar scan's time. GRLevel3 rejects the directive.
+    pub fn time_ranges(self) -> bool {
+        matches!(self, Dialect::Gr2Analyst | Dialect::GrEarth)
+    }
+}
+
+/// A preset set of colors used instead of the configured ones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Palette {
+    /// Colors that stay apart with deuteranopia and protanopia, from the Okabe–Ito palette.
+    Cvd,
+    /// Bright colors for dark radar backgrounds.
+    Dark,
+    /// Deep colors for light basemaps.
+    Light,
+}
+
+impl Palette {
+    fn parse(s: &str) -> HttpResult<Self> {
+        match s {
+            "cvd" => Ok(Palette::Cvd),
+            "dark" => Ok(Palette::Dark),
+            "light" => Ok(Palette::Light),
+            _ => Err(HttpError::BadRequest),
+        }
+    }
+}
+
+/// How a zip export is split into placefiles.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Split {
+    Day,
+    Month,
+}
+
+impl Split {
+    fn parse(s: &str) -> HttpResult<Self> {
+        match s {
+            "day" => Ok(Split::Day),
+            "month" => Ok(Split::Month),
+            _ => Err(HttpError::BadRequest),
+        }
+    }
+}
+
+/// The format of the files in a zip export.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Inner {
+    #[default]
+    Placefile,
+    GeoJson,
+    Csv,
+}
+
+impl Inner {
+    fn parse(s: &str) -> HttpResult<Self> {
+        match s {
+            "placefile" => Ok(Inner::Placefile),
+            "geojson" => Ok(Inner::GeoJson),
+            "csv" => Ok(Inner::Csv),
+            _ => Err(HttpError::BadRequest),
+        }
+    }
+
+    /// The extension of the files in the zip.
+    pub fn extension(self) -> &'static str {
+        match self {
+            Inner::Placefile => "txt",
+            Inner::GeoJson => "geojson",
+            Inner::Csv => "csv",
+        }
+    }
+}
+
+/// The format of the response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Format {
+    #[default]
+    Placefile,
+    /// A zip of files in the inner format.
+    Zip(Split, Inner),
+    GeoJson,
+    /// One row per warning, with the polygon as WKT.
+    Csv,
+}
+
+impl Format {
+    fn parse(format: &str, split: Split, inner: Inner) -> HttpResult<Self> {
+        match format {
+            "placefile" => Ok(Format::Placefile),
+            "zip" => Ok(Format::Zip(split, inner)),
+            "geojson" => Ok(Format::GeoJson),
+            "csv" => Ok(Format::Csv),
+            _ => Err(HttpError::BadRequest),
+        }
+    }
+
+    /// The format of the files written, which for a zip is the format of the files in it.
+    pub fn files(self) -> Inner {
+        match self {
+            Format::Placefile => Inner::Placefile,
+            Format::Zip(_, inner) => inner,
+            Format::GeoJson => Inner::GeoJson,
+            Format::Csv => Inner::Csv,
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Format::Placefile => "text/plain",
+            Format::Zip(..) => "application/zip",
+            Format::GeoJson => "application/geo+json",
+            Format::Csv => "text/csv",
+        }
+    }
+}
+
+/// Parses a simplification tolerance, which must be a positive number of degrees.
+fn parse_tolerance(s: &str) -> HttpResult<f32> {
+    match s.parse::<f32>() {
+        Ok(tolerance) if tolerance.is_finite() && tolerance > 0. => Ok(tolerance),
+        _ => Err(HttpError::BadRequest),
+    }
+}
+
+/// Parses a fill opacity, clamping it to the 0 to 255 a placefile accepts.
+fn parse_alpha(s: &str) -> HttpResult<u8> {
+    match s.parse::<f32>() {
+        Ok(alpha) if alpha.is_finite() => Ok(alpha.round().clamp(0., 255.) as u8),
+        _ => Err(HttpError::BadRequest),
+    }
+}
+
+/// Checks a user-supplied strftime format for labels, rejecting ones that are invalid or too long.
+fn parse_timefmt(s: &str) -> HttpResult<String> {
+    let mut items = chrono::format::StrftimeItems::new(s);
+    if s.is_empty()
+        || s.len() > TIMEFMT_MAX_LEN
+        || items.any(|item| item == chrono::format::Item::Error)
+    {
+        return Err(HttpError::BadRequest);
+    }
+
+    Ok(s.to_string())
+}
+
+/// Parses an on or off parameter, given as `1` or `0`.
+fn parse_flag(s: &str) -> HttpResult<bool> {
+    match s {
+        "1" | "true" => Ok(true),
+        "0" | "false" => Ok(false),
+        _ => Err(HttpError::BadRequest),
+    }
+}
+
+/// The coordinate reference system of GeoJSON output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Crs {
+    /// Longitude and latitude, EPSG:4326.
+    #[default]
+    Wgs84,
+    /// Web Mercator meters, EPSG:3857.
+    WebMercator,
+}
+
+impl Crs {
+    fn parse(s: &str) -> HttpResult<Self> {
+        let code = s.to_ascii_uppercase();
+        match code.strip_prefix("EPSG:").unwrap_or(&code) {
+            "4326" => Ok(Crs::Wgs84),
+            "3857" => Ok(Crs::WebMercator),
+            _ => Err(HttpError::BadRequest),
+        }
+    }
+
+    /// The name of the CRS, for the GeoJSON `crs` member.
+    pub fn urn(self) -> &'static str {
+        match self {
+            Crs::Wgs84 => "urn:ogc:def:crs:OGC:1.3:CRS84",
+            Crs::WebMercator => "urn:ogc:def:crs:EPSG::3857",
+        }
+    }
+}
+
+/// Parses a comma-separated list of offices, each with or without its leading letter, such as
+/// `OUN,KTSA`.
+fn parse_offices(s: &str) -> HttpResult<Vec<String>> {
+    s.split(',')
+        .map(|office| {
+            let office = office.trim().to_ascii_uppercase();
+            let letters = office.chars().all(|c| c.is_ascii_alphabetic());
+            match (3..=4).contains(&office.len()) && letters {
+                true => Ok(office),
+                false => Err(HttpError::BadRequest),
+            }
+        })
+        .collect()
+}
+
+/// Parses a duration of hours and minutes, such as `45m`, `1h` or `1h30m`. A bare number is in
+/// minutes.
+fn parse_duration(s: &str) -> HttpResult<chrono::Duration> {
+    if let Ok(minutes) = s.parse::<u32>() {
+        return Ok(chrono::Duration::minutes(minutes.into()));
+    }
+
+    let mut minutes = 0;
+    let mut rest = s.to_ascii_lowercase();
+    for (unit, scale) in [('h', 60), ('m', 1)] {
+        if let Some((number, after)) = rest.split_once(unit) {
+            let number: u32 = number.parse().map_err(|_| HttpError::BadRequest)?;
+            minutes += i64::from(number) * scale;
+            rest = after.to_string();
+        }
+    }
+
+    match rest.is_empty() {
+        true => Ok(chrono::Duration::minutes(minutes)),
+        false => Err(HttpError::BadRequest),
+    }
+}
+
+/// Parses a coordinate, which must be within `limit` degrees of 0.
+fn parse_degrees(s: &str, limit: f32) -> HttpResult<f32> {
+    match s.parse::<f32>() {
+        Ok(degrees) if (-limit..=limit).contains(&degrees) => Ok(degrees),
+        _ => Err(HttpError::BadRequest),
+    }
+}
+
+/// Parses a distance in miles, kilometers or nautical miles, such as `50mi`, `80km` or `40nm`, into
+/// kilometers. A bare number is in miles, and units are matched ignoring case.
+fn parse_radius(s: &str) -> HttpResult<f64> {
+    let s = s.trim().to_ascii_lowercase();
+    let (number, km) = [("mi", 1.609344), ("km", 1.), ("nm", 1.852)]
+        .into_iter()
+        .find_map(|(unit, km)| Some((s.strip_suffix(unit)?, km)))
+        .unwrap_or((&s, 1.609344));
+
+    match number.trim().parse::<f64>() {
+        Ok(radius) if radius.is_finite() && radius >= 0. => Ok(radius * km),
+        _ => Err(HttpError::BadRequest),
+    }
+}
+
+/// Tests if an office, such as `KOUN`, is in a list of offices given with or without their leading
+/// letter.
+fn is_office(office: &str, offices: &[String]) -> bool {
+    offices.iter().any(|code| office.ends_with(code.as_str()))
+}
+
+/// Builds a regex matching a phrase ignoring case, so texts don't need to be lowercased.
+fn contains_regex(phrase: &str) -> HttpResult<Regex> {
+    RegexBuilder::new(&regex::escape(phrase))
+        .case_insensitive(true)
+        .size_limit(REGEX_SIZE_LIMIT)
+        .build()
+        .map_err(|_| HttpError::BadRequest)
+}
+
+/// A range of time a request covers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Range {
+    /// The first and last days read, as their midnights unless a time within them was given.
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    /// The first and last times kept, if they are within a UTC day rather than a whole one.
+    pub after: Option<DateTime<Utc>>,
+    pub before: Option<DateTime<Utc>>,
+}
+
+impl Range {
+    /// A range of whole days.
+    pub fn days(start: DateTime<Utc>, end: DateTime<Utc>) -> Self {
+        Self {
+            start,
+            end,
+            after: None,
+            before: None,
+        }
+    }
+
+    /// The days read for the range.
+    fn dates(&self) -> impl Iterator<Item = NaiveDate> {
+        let end = self.end.date_naive();
+        self.start.date_naive().iter_days().take_while(move |&day| day <= end)
+    }
+
+    /// The end of the range, which for whole days is the midnight after the last one.
+    pub fn until(&self) -> DateTime<Utc> {
+        self.before.unwrap_or(self.end + chrono::Duration::days(1))
+    }
+
+    /// Tests if a time is within the times kept.
+    fn keeps(&self, time: DateTime<Utc>) -> bool {
+        self.after.is_none_or(|after| time >= after)
+            && self.before.is_none_or(|before| time <= before)
+    }
+
+    /// Tests if a time is within the range, counting only the days read when it covers whole days.
+    fn contains(&self, time: DateTime<Utc>) -> bool {
+        self.keeps(time) && time >= self.start && time < self.until()
+    }
+
+    /// Describes the range for people, with times only where they were given.
+    pub fn label(&self) -> String {
+        let format = |time: Option<DateTime<Utc>>, day: DateTime<Utc>| match time {
+            Some(time) => time.format("%Y-%m-%d %H:%MZ").to_string(),
+            None => day.format("%Y-%m-%d").to_string(),
+        };
+        let (start, end) = (format(self.after, self.start), format(self.before, self.end));
+        match start == end {
+            true => start,
+            false => format!("{start} to {end}"),
+        }
+    }
+}
+
+/// The options of a warnings request.
+#[derive(Debug, Clone, Default)]
+pub struct Query {
+    /// The ranges of time to read, in order of their starts.
+    pub ranges: Vec<Range>,
+    /// Only keep warnings containing this text, matched as a case-insensitive regex.
+    pub contains: Option<Regex>,
+    /// Only keep warnings matching this regex.
+    pub regex: Option<Regex>,
+    /// Only keep warnings in this layer.
+    pub layer: Option<Layer>,
+    /// Only keep warnings whose source contains this text, lowercased.
+    pub source: Option<String>,
+    /// Only keep warnings from these offices, with or without their leading letter.
+    pub wfo: Option<Vec<String>>,
+    /// Leave out warnings from these offices, with or without their leading letter.
+    pub exclude_wfo: Vec<String>,
+    /// Only keep warnings valid for at least this long.
+    pub min_duration: Option<chrono::Duration>,
+    /// Only keep warnings valid for at most this long.
+    pub max_duration: Option<chrono::Duration>,
+    /// Only keep warnings intersecting one of these areas, from a GeoJSON request body.
+    pub areas: Option<Vec<geo::Area>>,
+    /// Only keep warnings whose polygon comes within this many kilometers of a (lat, lon) point.
+    pub near: Option<((f32, f32), f64)>,
+    pub format: Format,
+    /// The CRS of GeoJSON output.
+    pub crs: Crs,
+    /// Simplifies polygons with this tolerance in degrees.
+    pub simplify: Option<f32>,
+    pub per_event: PerEvent,
+    pub mode: Mode,
+    /// Only keep warnings with a tornado report inside them while they were valid.
+    pub verified: bool,
+    /// Draws the warnings in these colors instead of the configured ones.
+    pub palette: Option<Palette>,
+    /// The strftime format of times in labels.
+    pub timefmt: Option<String>,
+    /// Adds a comment to placefiles counting the products that were skipped and why.
+    pub skips: bool,
+    /// Returns only the number of matching warnings instead of rendering them.
+    pub count: bool,
+    /// Fills every polygon at this opacity instead of the configured ones.
+    pub alpha: Option<u8>,
+    /// Adds a legend to placefiles.
+    pub legend: bool,
+    /// The application placefiles are made for, if the query names one.
+    pub dialect: Option<Dialect>,
+}
+
+impl Query {
+    /// Parses the query from a request url.
+    pub fn parse(url: &str) -> HttpResult<Self> {
+        Self::from_params(&parse_params(url)?)
+    }
+
+    /// Parses the query from the parameters of a request url.
+    pub fn from_params(params: &BTreeMap<String, String>) -> HttpResult<Self> {
+        check_params(params, PARAMS)?;
+        let ranges = parse_times(params)?;
+        let split = param(params, "split", Split::parse)?.unwrap_or(Split::Day);
+        let inner = param(params, "inner", Inner::parse)?;
+        let format = param(params, "format", |s| {
+            Format::parse(s, split, inner.unwrap_or_default())
+        })?
+        .unwrap_or_default();
+        if inner.is_some() && !matches!(format, Format::Zip(..)) {
+            return Err(HttpError::Invalid("inner only applies to format=zip".to_string()));
+        }
+        let crs = param(params, "crs", Crs::parse)?.unwrap_or_default();
+        let mode = param(params, "mode", Mode::parse)?.unwrap_or_default();
+
+        // Placefiles are always in latitude and longitude, and CSV rows are warnings, not tracks.
+        if crs != Crs::Wgs84 && format.files() != Inner::GeoJson {
+            return Err(HttpError::Invalid("crs only applies to GeoJSON".to_string()));
+        }
+        if mode == Mode::Track && format.files() == Inner::Csv {
+            return Err(HttpError::Invalid("mode=track can't be used with CSV".to_string()));
+        }
+        let legend = param(params, "legend", parse_flag)?.unwrap_or(false);
+        if legend && format != Format::Placefile {
+            return Err(HttpError::Invalid("legend only applies to format=placefile".to_string()));
+        }
+        let dialect = param(params, "dialect", Dialect::parse)?;
+        if dialect.is_some() && format.files() != Inner::Placefile {
+            return Err(HttpError::Invalid("dialect only applies to placefiles".to_string()));
+        }
+        let near = match (
+            param(params, "lat", |s| parse_degrees(s, 90.))?,
+            param(params, "lon", |s| parse_degrees(s, 180.))?,
+            param(params, "radius", parse_radius)?,
+        ) {
+            (Some(lat), Some(lon), Some(radius)) => Some(((lat, lon), radius)),
+            (None, None, None) => None,
+            _ => {
+                let reason = "lat, lon and radius must be given together";
+                return Err(HttpError::Invalid(reason.to_string()));
+            }
+        };
+        let alpha = param(params, "alpha", parse_alpha)?;
+        if alpha.is_some() && mode != Mode::Fill {
+            return Err(HttpError::Invalid("alpha only applies to mode=fill".to_string()));
+        }
+
+        Ok(Self {
+            ranges,
+            contains: param(params, "contains", contains_regex)?,
+            regex: param(params, "regex", user_regex)?,
+            layer: param(params, "layer", Layer::parse)?,
+            source: params.get("source").map(|s| s.to_lowercase()),
+            wfo: param(params, "wfo", parse_offices)?,
+            exclude_wfo: param(params, "exclude_wfo", parse_offices)?.unwrap_or_default(),
+            min_duration: param(params, "min_duration", parse_duration)?,
+            max_duration: param(params, "max_duration", parse_duration)?,
+            areas: None,
+            near,
+            format,
+            crs,
+            simplify: param(params, "simplify", parse_tolerance)?,
+            per_event: param(params, "per_event", PerEvent::parse)?.unwrap_or_default(),
+            mode,
+            verified: param(params, "verified", parse_flag)?.unwrap_or(false),
+            palette: param(params, "palette", Palette::parse)?,
+            timefmt: param(params, "timefmt", parse_timefmt)?,
+            skips: param(params, "skips", parse_flag)?.unwrap_or(false),
+            count: param(params, "count", parse_flag)?.unwrap_or(false),
+            alpha,
+            legend,
+            dialect,
+        })
+    }
+
+    /// A query for every warning in a range.
+    pub fn range(start: DateTime<Utc>, end: DateTime<Utc>) -> Self {
+        Self {
+            ranges: vec![Range::days(start, end)],
+            ..Default::default()
+        }
+    }
+
+    /// A query for every product of one VTEC tornado warning event.
+    ///
+    /// The office can be given with or without its leading letter, such as `OUN` or `KOUN`. Only
+    /// the days around the ones the index has seen the event on are read, or else the whole year.
+    /// Either way the first hours of the next year are kept, for events issued late on Dec 31.
+    pub fn event(year: i32, office: &str, etn: u32) -> HttpResult<Self> {
+        let office = office.to_ascii_uppercase();
+        if !(3..=4).contains(&office.len()) || !office.chars().all(|c| c.is_ascii_alphabetic()) {
+            let reason = format!("invalid office: {office}, expected 3 or 4 letters like OUN");
+            return Err(HttpError::Invalid(reason));
+        }
+
+        let invalid_year = || HttpError::Invalid(format!("invalid year: {year}"));
+        let start = NaiveDate::from_ymd_opt(year, 1, 1).ok_or_else(invalid_year)?;
+        let next_year = NaiveDate::from_ymd_opt(year + 1, 1, 1).ok_or_else(invalid_year)?;
+        let today = Utc::now().date_naive();
+        if year < FIRST_EVENT_YEAR || start > today {
+            return Err(HttpError::NotFound);
+        }
+        let midnight = |date: NaiveDate| date.and_time(NaiveTime::MIN).and_utc();
+
+        let seen = index::event_days(&office, etn, start, next_year);
+        let (first, last) = match (seen.first(), seen.last()) {
+            (Some(first), Some(last)) => (first.pred_opt().unwrap(), last.succ_opt().unwrap()),
+            _ => (start, next_year),
+        };
+        let last = last.min(next_year).min(today);
+
+        let prefix = if office.len() == 3 { "[A-Z]" } else { "" };
+        let vtec = format!(r"/[OTEX]\.[A-Z]{{3}}\.{prefix}{office}\.TO\.W\.{etn:04}\.");
+
+        // Numbers start over each year, so a new event reusing this one's is left out.
+        let range = Range {
+            before: (last == next_year)
+                .then(|| midnight(next_year) + chrono::Duration::hours(EVENT_OVERRUN_HOURS)),
+            ..Range::days(midnight(first.max(start)), midnight(last))
+        };
+
+        Ok(Self {
+            regex: Some(Regex::new(&vtec).unwrap()),
+            ranges: vec![range],
+            ..Default::default()
+        })
+    }
+
+    /// The content type of the response, which is JSON for counts asked for with format=geojson.
+    pub fn content_type(&self) -> &'static str {
+        match (self.count, self.format) {
+            (true, Format::GeoJson) => "application/json",
+            (true, _) => "text/plain",
+            (false, format) => format.content_type(),
+        }
+    }
+
+    /// The days of files the query reads, in order and without the ones ranges share.
+    pub fn dates(&self) -> Vec<NaiveDate> {
+        let mut dates: Vec<_> = self.ranges.iter().flat_map(Range::dates).collect();
+        dates.sort_unstable();
+        dates.dedup();
+        dates
+    }
+
+    /// How many days of files the query reads.
+    pub fn days(&self) -> i64 {
+        self.dates().len() as i64
+    }
+
+    /// The last day read, or the last time if it was given with one.
+    pub fn end(&self) -> DateTime<Utc> {
+        self.ranges.iter().map(|range| range.end).max().unwrap_or_default()
+    }
+
+    /// The range a time falls in, when the query has more than one to tell apart.
+    pub fn range_of(&self, time: DateTime<Utc>) -> Option<&Range> {
+        match self.ranges.len() {
+            0 | 1 => None,
+            _ => self.ranges.iter().find(|range| range.contains(time)),
+        }
+    }
+
+    /// Whether placefiles should give each warning a `TimeRange:`.
+    pub fn time_ranges(&self) -> bool {
+        self.dialect.is_some_and(Dialect::time_ranges)
+    }
+
+    /// The strftime format of times in labels.
+    pub fn timefmt(&self) -> &str {
+        self.timefmt.as_deref().unwrap_or(DEFAULT_TIMEFMT)
+    }
+
+    /// Tests if a product's raw text passes the filters that only need the text.
+    ///
+    /// This runs before parsing, so products that are filtered out are never copied.
+    pub fn matches_text(&self, text: &str) -> bool {
+        self.contains.as_ref().is_none_or(|regex| regex.is_match(text))
+            && self.regex.as_ref().is_none_or(|regex| regex.is_match(text))
+    }
+
+    /// Tests if a warning is valid for as long as the duration filters ask, if they are set.
+    ///
+    /// Warnings without an expiration have no known duration, so they only pass without them.
+    fn duration_matches(&self, warning: &Warning) -> bool {
+        if self.min_duration.is_none() && self.max_duration.is_none() {
+            return true;
+        }
+
+        warning.duration().is_some_and(|duration| {
+            self.min_duration.is_none_or(|min| duration >= min)
+                && self.max_duration.is_none_or(|max| duration <= max)
+        })
+    }
+
+    /// Tests if a parsed warning passes the rest of the filters.
+    pub fn matches(&self, warning: &Warning) -> bool {
+        // A single range keeps whatever was read for it, but with several, the whole days of one
+        // mustn't let in warnings that another range cuts off.
+        let in_range = match &self.ranges[..] {
+            [] => true,
+            [range] => range.keeps(warning.issued),
+            ranges => ranges.iter().any(|range| range.contains(warning.issued)),
+        };
+
+        let office = warning.vtec.as_ref().map_or("", |vtec| vtec.office.as_str());
+
+        in_range
+            && self.wfo.as_ref().is_none_or(|offices| is_office(office, offices))
+            && !is_office(office, &self.exclude_wfo)
+            && self.duration_matches(warning)
+            && self.areas.as_ref().is_none_or(|areas| {
+                areas.iter().any(|area| geo::intersects(&warning.polygon, area))
+            })
+            && self.near.is_none_or(|(point, radius)| {
+                geo::great_circle_to_polygon(point, &warning.polygon) <= radius
+            })
+            && self
+                .layer
+                .is_none_or(|layer| (layer == Layer::Observed) == warning.observed)
+            && self.source.as_ref().is_none_or(|needle| {
+                warning
+                    .source
+                    .as_ref()
+                    .is_some_and(|source| source.to_lowercase().contains(needle))
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn invalid_events() {
+        let reason = |result: HttpResult<Query>| match result {
+            Err(HttpError::Invalid(reason)) => reason,
+            _ => panic!("expected an invalid request"),
+        };
+
+        assert!(reason(Query::event(2013, "OKLAH", 45)).starts_with("invalid office: OKLAH"));
+        assert!(reason(Query::event(2013, "O1N", 45)).starts_with("invalid office: O1N"));
+        let year = i32::MAX;
+        assert_eq!(reason(Query::event(year, "OUN", 45)), format!("invalid year: {year}"));
+        assert!(usage("/event/2013/O1N/45.txt").starts_with("Usage: /event/"));
+    }
+
+    #[test]
+    fn radius_units_ignore_case() {
+        assert_eq!(parse_radius("80km").unwrap(), 80.);
+        assert_eq!(parse_radius("80KM").unwrap(), 80.);
+        assert_eq!(parse_radius("10 Mi").unwrap(), 16.09344);
+        assert_eq!(parse_radius("10").unwrap(), 16.09344);
+        assert!((parse_radius("10NM").unwrap() - 18.52).abs() < 1e-9);
+        assert!(parse_radius("10 ft").is_err());
+        assert!(parse_radius("-1km").is_err());
+    }
+}
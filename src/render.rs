@@ -0,0 +1,294 @@
+use crate::config::{Colors, Config, Icons, Style};
+use crate::event::Event;
+use crate::geo;
+use crate::query::{Query, Range};
+use crate::warning::{Severity, Skips, Warning};
+use chrono::{DateTime, Utc};
+use std::io::Write;
+
+/// Picks the style for a warning. Waterspouts stand out unless the warning is more severe, and
+/// unverified warnings can be set apart from the rest.
+pub fn style<'a>(warning: &Warning, colors: &'a Colors) -> &'a Style {
+    let unverified = colors.unverified.as_ref();
+    if let Some(unverified) = unverified.filter(|_| warning.verified == Some(false)) {
+        return unverified;
+    }
+
+    match warning.severity {
+        Severity::Observed | Severity::Default if warning.waterspout => &colors.waterspout,
+        severity => severity.style(colors),
+    }
+}
+
+/// Builds the hover text shown for a warning, with times in the given strftime format.
+pub fn hover(warning: &Warning, timefmt: &str) -> String {
+    let mut hover = format!("Issued {}", warning.issued.format(timefmt));
+
+    if let Some(source) = &warning.source {
+        hover += &format!("\\nSource: {source}");
+    }
+    if let Some(hail) = warning.max_hail_size {
+        hover += &format!("\\nMax hail size {hail:.2} in");
+    }
+    if let Some(wind) = warning.max_wind_gust {
+        hover += &format!("\\nMax wind gust {wind} mph");
+    }
+    match warning.verified {
+        Some(true) => hover += "\\nVerified by a tornado report",
+        Some(false) => hover += "\\nNo tornado reported",
+        None => (),
+    }
+
+    // Quotes would end the hover text early.
+    hover.replace('"', "'")
+}
+
+/// Splits a path into dashes of the given length in degrees, with equal gaps between them.
+fn dashes(path: &[(f32, f32)], length: f32) -> Vec<Vec<(f32, f32)>> {
+    let mut dashes = Vec::new();
+    let mut dash = vec![path[0]];
+    let mut drawing = true;
+    // How far along the current dash or gap we are.
+    let mut travelled = 0.;
+
+    for edge in path.windows(2) {
+        let ((lat0, lon0), (lat1, lon1)) = (edge[0], edge[1]);
+        let edge_length = (lat1 - lat0).hypot(lon1 - lon0);
+        let mut along = 0.;
+
+        while edge_length - along > length - travelled {
+            along += length - travelled;
+            travelled = 0.;
+
+            let t = along / edge_length;
+            let point = (lat0 + (lat1 - lat0) * t, lon0 + (lon1 - lon0) * t);
+
+            dash.push(point);
+            if drawing {
+                dashes.push(std::mem::take(&mut dash));
+            }
+            drawing = !drawing;
+        }
+
+        travelled += edge_length - along;
+        if drawing {
+            dash.push(edge[1]);
+        }
+    }
+
+    if drawing && dash.len() > 1 {
+        dashes.push(dash);
+    }
+
+    dashes
+}
+
+/// Writes a warning's outline, split into dashes if its style asks for them.
+pub fn outline(writer: &mut Vec<u8>, polygon: &[(f32, f32)], style: &Style, hover: &str) {
+    let Style {
+        color, width, dash, ..
+    } = style;
+
+    let lines = match dash {
+        Some(length) if *length > 0. => dashes(polygon, *length),
+        _ => vec![polygon.to_vec()],
+    };
+
+    writeln!(writer, "Color: {color}").unwrap();
+    for line in lines {
+        writeln!(writer, "Line: {width}, 0, \"{hover}\"").unwrap();
+        for (lat, lon) in line {
+            writeln!(writer, "{lat}, {lon}").unwrap();
+        }
+        writeln!(writer, "End:\n").unwrap();
+    }
+}
+
+/// Limits the objects after it to radar scans between two times.
+pub fn time_range(writer: &mut Vec<u8>, start: DateTime<Utc>, end: DateTime<Utc>) {
+    const FORMAT: &str = "%Y-%m-%dT%H:%M:%SZ";
+    writeln!(writer, "TimeRange: {} {}", start.format(FORMAT), end.format(FORMAT)).unwrap();
+}
+
+/// Writes a warning's polygon filled in its style's color, as triangles.
+pub fn fill(writer: &mut Vec<u8>, polygon: &[(f32, f32)], style: &Style) {
+    // Triangle vertices take the color as comma separated components, without any alpha of its own.
+    let color: Vec<_> = style.color.split_whitespace().take(3).collect();
+    let color = color.join(", ");
+
+    writeln!(writer, "Triangles:").unwrap();
+    for triangle in geo::triangulate(polygon) {
+        for (lat, lon) in triangle {
+            writeln!(writer, "{lat}, {lon}, {color}, {}", style.fill_alpha).unwrap();
+        }
+    }
+    writeln!(writer, "End:\n").unwrap();
+}
+
+/// Declares the icon sheet, so icons can be drawn later in the placefile.
+pub fn icon_file(writer: &mut Vec<u8>, icons: &Icons) {
+    let Icons {
+        file, width, height, ..
+    } = icons;
+    writeln!(
+        writer,
+        "IconFile: 1, {width}, {height}, {}, {}, \"{file}\"\n",
+        width / 2,
+        height / 2
+    )
+    .unwrap();
+}
+
+/// Writes the icons that mark a warning, if any apply to it.
+pub fn icons(writer: &mut Vec<u8>, warning: &Warning, icons: &Icons, hover: &str) {
+    if let Some(number) = icons.waterspout.filter(|_| warning.waterspout) {
+        let (lat, lon) = geo::centroid(&warning.polygon);
+        writeln!(writer, "Icon: {lat}, {lon}, 0, 1, {number}, \"{hover}\"\n").unwrap();
+    }
+
+    if let (Some(number), Some(motion)) = (icons.motion, &warning.motion) {
+        // The direction is where the storm is coming from, so it heads the opposite way.
+        let heading = (motion.direction + 180) % 360;
+        let hover = format!(
+            "Moving toward {heading} deg at {} kt as of {}\\n{hover}",
+            motion.speed, motion.time
+        );
+        for (lat, lon) in &motion.locations {
+            writeln!(writer, "Object: {lat}, {lon}").unwrap();
+            writeln!(writer, "Icon: 0, 0, {heading}, 1, {number}, \"{hover}\"").unwrap();
+            writeln!(writer, "End:\n").unwrap();
+        }
+    }
+}
+
+/// The title of a placefile.
+fn title(query: &Query) -> String {
+    match query.layer {
+        Some(layer) => format!("Past TORs ({})", layer.title()),
+        None => "Past TORs".to_string(),
+    }
+}
+
+/// Starts a placefile, returning the header.
+pub fn header(query: &Query, config: &Config) -> Vec<u8> {
+    let mut writer = Vec::new();
+    let title = title(query);
+    writeln!(&mut writer, "Title: {title}\nRefresh: {}\n", config.refresh).unwrap();
+    if let Some(icons) = &config.icons {
+        icon_file(&mut writer, icons);
+    }
+
+    writer
+}
+
+/// Writes one warning to a placefile.
+pub fn warning(writer: &mut Vec<u8>, warning: &Warning, config: &Config, timefmt: &str) {
+    let hover = hover(warning, timefmt);
+    outline(writer, &warning.polygon, style(warning, &config.colors), &hover);
+    if let Some(icons) = &config.icons {
+        self::icons(writer, warning, icons, &hover);
+    }
+}
+
+/// Starts the live placefile, which refreshes as often as live mode polls.
+pub fn live_header(config: &Config) -> Vec<u8> {
+    let mut writer = Vec::new();
+    writeln!(
+        &mut writer,
+        "Title: Live TORs\nRefreshSeconds: {}\n",
+        config.live.interval
+    )
+    .unwrap();
+    if let Some(icons) = &config.icons {
+        icon_file(&mut writer, icons);
+    }
+
+    writer
+}
+
+/// Writes a warning that recently expired, dimmed and labeled as such.
+pub fn expired(writer: &mut Vec<u8>, warning: &Warning, config: &Config, timefmt: &str) {
+    let hover = format!("EXPIRED\\n{}", hover(warning, timefmt));
+    outline(writer, &warning.polygon, &config.colors.expired, &hover);
+}
+
+/// Writes a legend saying what a placefile shows and when it was generated, in white text below
+/// the northwest corner of its warnings.
+///
+/// Placefiles can only place text on the map, so the corner keeps it next to what it describes.
+/// It is also written as comments, which are there even without any warnings. The legend goes
+/// right after the header, so it is the first thing in the file.
+pub fn legend(
+    writer: &mut Vec<u8>,
+    query: &Query,
+    severities: &[(Severity, usize)],
+    corner: Option<(f32, f32)>,
+) {
+    let count: usize = severities.iter().map(|(_, count)| count).sum();
+    let ranges: Vec<_> = query.ranges.iter().map(Range::label).collect();
+    let kinds: Vec<_> = severities
+        .iter()
+        .filter(|(_, count)| *count > 0)
+        .map(|(severity, count)| format!("{count} {}", severity.name()))
+        .collect();
+
+    let mut lines = vec![title(query), ranges.join(", "), format!("{count} tornado warnings")];
+    if !kinds.is_empty() {
+        lines.push(kinds.join(", "));
+    }
+    lines.push(format!("Generated {}", Utc::now().format("%Y-%m-%d %H:%MZ")));
+
+    for line in &lines {
+        writeln!(writer, "; {line}").unwrap();
+    }
+    writeln!(writer).unwrap();
+
+    if let Some((lat, lon)) = corner {
+        // The legend describes the whole range, so it shows with any scan in it.
+        if query.time_ranges() {
+            let start = query.ranges.iter().map(|range| range.start).min();
+            let until = query.ranges.iter().map(Range::until).max();
+            if let (Some(start), Some(until)) = (start, until) {
+                time_range(writer, start, until);
+            }
+        }
+        writeln!(writer, "Font: 1, 12, 1, \"Arial\"").unwrap();
+        writeln!(writer, "Object: {lat}, {lon}\nColor: 255 255 255").unwrap();
+        for (i, line) in lines.iter().enumerate() {
+            let y = -15 * (i as i32 + 1);
+            writeln!(writer, "Text: 0, {y}, 1, \"{}\"", line.replace('"', "'")).unwrap();
+        }
+        writeln!(writer, "End:\n").unwrap();
+    }
+}
+
+/// Writes a comment summarizing the products left out of a placefile and why.
+pub fn skips(writer: &mut Vec<u8>, skips: &Skips) {
+    let total: usize = skips.values().sum();
+    writeln!(writer, "; Skipped {total} products").unwrap();
+    for (skip, count) in skips {
+        writeln!(writer, ";   {}: {count}", skip.name()).unwrap();
+    }
+}
+
+/// Writes an event's storm track, a line through the centroids of its successive polygons.
+pub fn track(
+    writer: &mut Vec<u8>,
+    event: &Event,
+    track: &[(f32, f32)],
+    config: &Config,
+    timefmt: &str,
+) {
+    let latest = event.latest();
+    let mut hover = match &event.key {
+        Some(key) => format!("{} event {}", key.office, key.etn),
+        None => "Event".to_string(),
+    };
+    hover += &format!("\\nIssued {}", event.first().issued.format(timefmt));
+    if let Some(expires) = event.expires() {
+        hover += &format!("\\nExpires {}", expires.format(timefmt));
+    }
+    hover += &format!("\\n{} products", event.warnings.len());
+
+    outline(writer, track, style(latest, &config.colors), &hover.replace('"', "'"));
+}
@@ -0,0 +1,199 @@
+use chrono::{DateTime, Datelike, Duration, Timelike, Utc};
+use serde::Deserialize;
+
+/// The values one field of a schedule matches, as a bit per value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Field(u64);
+
+impl Field {
+    /// Parses a field such as `*`, `*/5`, `6`, `1-5` or `0,30`, with values from `min` to `max`.
+    fn parse(text: &str, min: u32, max: u32) -> Result<Self, String> {
+        let mut bits = 0;
+        for part in text.split(',') {
+            let (range, step) = match part.split_once('/') {
+                Some((range, step)) => match step.parse() {
+                    Ok(step) if step > 0 => (range, step),
+                    _ => return Err(format!("invalid step in {part}")),
+                },
+                None => (part, 1),
+            };
+
+            let value = |text: &str| match text.parse() {
+                Ok(value) if (min..=max).contains(&value) => Ok(value),
+                _ => Err(format!("{text} is not between {min} and {max}")),
+            };
+            let (first, last) = match range {
+                "*" => (min, max),
+                range => match range.split_once('-') {
+                    Some((first, last)) => (value(first)?, value(last)?),
+                    // A single value with a step, like `5/15`, runs to the end of the range.
+                    None if step > 1 => (value(range)?, max),
+                    None => (value(range)?, value(range)?),
+                },
+            };
+
+            for value in (first..=last).step_by(step) {
+                bits |= 1 << value;
+            }
+        }
+
+        Ok(Self(bits))
+    }
+
+    fn matches(self, value: u32) -> bool {
+        self.0 & (1 << value) != 0
+    }
+}
+
+/// A cron schedule: minute, hour, day of month, month and day of week, in UTC.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Schedule {
+    minute: Field,
+    hour: Field,
+    day: Field,
+    month: Field,
+    weekday: Field,
+    /// Whether the day of month or week was restricted, since cron matches either one then.
+    any_day: bool,
+}
+
+impl Schedule {
+    /// Tests if the schedule fires on the day containing `time`.
+    fn matches_day(&self, time: DateTime<Utc>) -> bool {
+        let day = self.day.matches(time.day());
+        let weekday = self.weekday.matches(time.weekday().num_days_from_sunday());
+        let day = match self.any_day {
+            true => day || weekday,
+            false => day && weekday,
+        };
+
+        self.month.matches(time.month()) && day
+    }
+
+    /// Finds the first minute after the one containing `time` that the schedule fires in.
+    ///
+    /// Returns nothing for schedules that never fire, such as on February 30th.
+    pub fn next_after(&self, time: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let mut next = time.with_second(0)?.with_nanosecond(0)? + Duration::minutes(1);
+        // Every valid day of the month comes around within a leap year cycle.
+        let limit = next + Duration::days(4 * 366);
+
+        while next < limit {
+            if !self.matches_day(next) {
+                let tomorrow = next.date_naive() + Duration::days(1);
+                next = tomorrow.and_hms_opt(0, 0, 0)?.and_utc();
+            } else if !self.hour.matches(next.hour()) {
+                next = next.with_minute(0)? + Duration::hours(1);
+            } else if !self.minute.matches(next.minute()) {
+                next += Duration::minutes(1);
+            } else {
+                return Some(next);
+            }
+        }
+
+        None
+    }
+}
+
+impl TryFrom<String> for Schedule {
+    type Error = String;
+
+    fn try_from(text: String) -> Result<Self, String> {
+        let fields: Vec<_> = text.split_whitespace().collect();
+        let [minute, hour, day, month, weekday] = fields[..] else {
+            return Err(format!("expected 5 fields in schedule \"{text}\""));
+        };
+
+        let error = |e| format!("invalid schedule \"{text}\": {e}");
+        // Sunday can be written as 0 or 7.
+        let mut weekdays = Field::parse(weekday, 0, 7).map_err(error)?;
+        if weekdays.matches(7) {
+            weekdays.0 |= 1;
+        }
+
+        Ok(Self {
+            minute: Field::parse(minute, 0, 59).map_err(error)?,
+            hour: Field::parse(hour, 0, 23).map_err(error)?,
+            day: Field::parse(day, 1, 31).map_err(error)?,
+            month: Field::parse(month, 1, 12).map_err(error)?,
+            weekday: weekdays,
+            any_day: day != "*" && weekday != "*",
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn schedule(text: &str) -> Schedule {
+        Schedule::try_from(text.to_string()).unwrap()
+    }
+
+    fn utc(y: i32, m: u32, d: u32, h: u32, min: u32, s: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(y, m, d, h, min, s).unwrap()
+    }
+
+    #[test]
+    fn fields() {
+        assert_eq!(Field::parse("*", 0, 3).unwrap(), Field(0b1111));
+        assert_eq!(Field::parse("*/15", 0, 59).unwrap(), Field(1 | 1 << 15 | 1 << 30 | 1 << 45));
+        assert_eq!(Field::parse("1-3,5", 0, 6).unwrap(), Field(0b101110));
+        assert_eq!(Field::parse("5/20", 0, 59).unwrap(), Field(1 << 5 | 1 << 25 | 1 << 45));
+
+        assert!(Field::parse("60", 0, 59).is_err());
+        assert!(Field::parse("*/0", 0, 59).is_err());
+        assert!(Field::parse("a-b", 0, 59).is_err());
+    }
+
+    #[test]
+    fn invalid_schedules() {
+        assert!(Schedule::try_from("* * *".to_string()).is_err());
+        assert!(Schedule::try_from("0 24 * * *".to_string()).is_err());
+        assert!(Schedule::try_from("0 0 0 * *".to_string()).is_err());
+    }
+
+    #[test]
+    fn next_minutes() {
+        let daily = schedule("0 6 * * *");
+        assert_eq!(daily.next_after(utc(2024, 5, 20, 5, 59, 30)), Some(utc(2024, 5, 20, 6, 0, 0)));
+        // The minute containing the time is never the next one.
+        assert_eq!(daily.next_after(utc(2024, 5, 20, 6, 0, 0)), Some(utc(2024, 5, 21, 6, 0, 0)));
+
+        let quarters = schedule("*/15 * * * *");
+        assert_eq!(
+            quarters.next_after(utc(2024, 12, 31, 23, 50, 0)),
+            Some(utc(2025, 1, 1, 0, 0, 0))
+        );
+    }
+
+    #[test]
+    fn days_of_the_week() {
+        // May 20th 2024 was a Monday.
+        let mondays = schedule("30 12 * * 1");
+        let next = mondays.next_after(utc(2024, 5, 20, 13, 0, 0));
+        assert_eq!(next, Some(utc(2024, 5, 27, 12, 30, 0)));
+
+        let sundays = schedule("0 0 * * 7");
+        assert_eq!(sundays.next_after(utc(2024, 5, 20, 0, 0, 0)), Some(utc(2024, 5, 26, 0, 0, 0)));
+    }
+
+    #[test]
+    fn either_day_when_both_are_given() {
+        // Cron fires on the 13th or on Fridays, not only on Friday the 13th.
+        let schedule = schedule("0 0 13 * 5");
+        assert_eq!(schedule.next_after(utc(2024, 5, 20, 0, 0, 0)), Some(utc(2024, 5, 24, 0, 0, 0)));
+        assert_eq!(schedule.next_after(utc(2024, 6, 8, 0, 0, 0)), Some(utc(2024, 6, 13, 0, 0, 0)));
+    }
+
+    #[test]
+    fn leap_days_and_impossible_days() {
+        let leap_days = schedule("0 0 29 2 *");
+        let next = leap_days.next_after(utc(2024, 5, 20, 0, 0, 0));
+        assert_eq!(next, Some(utc(2028, 2, 29, 0, 0, 0)));
+
+        assert_eq!(schedule("0 0 30 2 *").next_after(utc(2024, 5, 20, 0, 0, 0)), None);
+    }
+}
@@ -0,0 +1,206 @@
+use crate::config::Config;
+use crate::geo;
+use crate::warning::Warning;
+use chrono::{DateTime, Duration, NaiveDate, NaiveDateTime, NaiveTime, Utc};
+use lazy_static::lazy_static;
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+/// A tornado reported at a place and time.
+#[derive(Debug, Clone, Copy)]
+pub struct Report {
+    pub time: DateTime<Utc>,
+    pub lat: f32,
+    pub lon: f32,
+}
+
+/// Reports sorted by time, so the ones during a warning can be found quickly.
+pub type Reports = Arc<Vec<Report>>;
+
+/// The columns used from an NCEI Storm Events details file.
+#[derive(Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+struct StormEvent {
+    begin_yearmonth: u32,
+    begin_day: u32,
+    begin_time: u32,
+    event_type: String,
+    /// The timezone the times are in, such as `CST-6`.
+    cz_timezone: String,
+    begin_lat: Option<f32>,
+    begin_lon: Option<f32>,
+    end_lat: Option<f32>,
+    end_lon: Option<f32>,
+}
+
+impl StormEvent {
+    /// Converts a tornado event to reports at its start and end points.
+    fn reports(&self) -> Vec<Report> {
+        if self.event_type != "Tornado" {
+            return Vec::new();
+        }
+
+        // The offset is the number after the zone name, such as -6 in `CST-6`.
+        let offset: i64 = self
+            .cz_timezone
+            .trim_start_matches(|c: char| c.is_ascii_alphabetic())
+            .parse()
+            .unwrap_or(0);
+        let date = NaiveDate::from_ymd_opt(
+            (self.begin_yearmonth / 100) as i32,
+            self.begin_yearmonth % 100,
+            self.begin_day,
+        );
+        let time = NaiveTime::from_hms_opt(self.begin_time / 100, self.begin_time % 100, 0);
+        let Some((date, time)) = date.zip(time) else {
+            return Vec::new();
+        };
+        let time = date.and_time(time).and_utc() - Duration::hours(offset);
+
+        [
+            (self.begin_lat, self.begin_lon),
+            (self.end_lat, self.end_lon),
+        ]
+        .into_iter()
+        .filter_map(|(lat, lon)| {
+            Some(Report {
+                time,
+                lat: lat?,
+                lon: lon?,
+            })
+        })
+        .collect()
+    }
+}
+
+/// Reads the tornadoes from a Storm Events details CSV, or every CSV in a directory.
+fn read_storm_events(path: &Path) -> Result<Vec<Report>, String> {
+    let files = if path.is_dir() {
+        std::fs::read_dir(path)
+            .map_err(|e| e.to_string())?
+            .filter_map(|entry| Some(entry.ok()?.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == "csv"))
+            .collect()
+    } else {
+        vec![path.to_path_buf()]
+    };
+
+    let mut reports = Vec::new();
+    for file in files {
+        let mut reader = csv::Reader::from_path(&file).map_err(|e| e.to_string())?;
+        for event in reader.deserialize::<StormEvent>() {
+            let event = event.map_err(|e| format!("{}: {e}", file.display()))?;
+            reports.extend(event.reports());
+        }
+    }
+
+    reports.sort_by_key(|report| report.time);
+    Ok(reports)
+}
+
+/// A GeoJSON collection of Local Storm Reports, as served by IEM.
+#[derive(Deserialize)]
+struct LsrCollection {
+    features: Vec<LsrFeature>,
+}
+
+#[derive(Deserialize)]
+struct LsrFeature {
+    /// The point as longitude and latitude.
+    geometry: LsrPoint,
+    properties: LsrProperties,
+}
+
+#[derive(Deserialize)]
+struct LsrPoint {
+    coordinates: (f32, f32),
+}
+
+#[derive(Deserialize)]
+struct LsrProperties {
+    /// When the event happened, such as `2022-05-01T22:40:00Z`.
+    valid: String,
+    /// The event type, such as `TORNADO` or `HAIL`.
+    typetext: String,
+}
+
+/// Reads the tornadoes from a GeoJSON collection of Local Storm Reports, sorted by time.
+pub fn parse_lsrs(json: &str) -> Result<Vec<Report>, String> {
+    let collection: LsrCollection = serde_json::from_str(json).map_err(|e| e.to_string())?;
+
+    let mut reports = Vec::new();
+    for feature in collection.features {
+        if !feature.properties.typetext.eq_ignore_ascii_case("tornado") {
+            continue;
+        }
+
+        let valid = &feature.properties.valid;
+        let time = DateTime::parse_from_rfc3339(valid)
+            .map(|time| time.to_utc())
+            .or_else(|_| {
+                NaiveDateTime::parse_from_str(valid, "%Y-%m-%dT%H:%M:%S").map(|time| time.and_utc())
+            })
+            .map_err(|e| format!("invalid report time {valid}: {e}"))?;
+        let (lon, lat) = feature.geometry.coordinates;
+        reports.push(Report { time, lat, lon });
+    }
+
+    reports.sort_by_key(|report| report.time);
+    Ok(reports)
+}
+
+lazy_static! {
+    /// The path Storm Events were last loaded from, and the reports.
+    static ref STORM_EVENTS: RwLock<Option<(PathBuf, Reports)>> = RwLock::new(None);
+}
+
+/// Loads the Storm Events tornadoes from the configured path, such as at startup or when the
+/// config is reloaded, which also picks up files added to the path since.
+///
+/// If loading fails, the reports are dropped until the next reload.
+pub fn load(config: &Config) {
+    let reports = config.storm_events.as_ref().and_then(|path| {
+        match read_storm_events(path) {
+            Ok(reports) => {
+                println!(
+                    "Loaded {} tornado reports from {}",
+                    reports.len(),
+                    path.display()
+                );
+                Some((path.clone(), Arc::new(reports)))
+            }
+            Err(e) => {
+                eprintln!("Failed to load storm events from {}: {e}", path.display());
+                None
+            }
+        }
+    });
+
+    *STORM_EVENTS.write().unwrap() = reports;
+}
+
+/// Returns the Storm Events tornadoes loaded from the configured path, if they loaded.
+pub fn storm_events(config: &Config) -> Option<Reports> {
+    let path = config.storm_events.as_ref()?;
+    match &*STORM_EVENTS.read().unwrap() {
+        Some((loaded_path, reports)) if loaded_path == path => Some(reports.clone()),
+        _ => None,
+    }
+}
+
+/// Tests if any report falls inside a warning's polygon while it was valid.
+pub fn verified(warning: &Warning, reports: &[Report]) -> bool {
+    let start = warning.issued;
+    let end = warning
+        .vtec
+        .as_ref()
+        .and_then(|vtec| vtec.end)
+        .unwrap_or(start + Duration::hours(1));
+
+    let first = reports.partition_point(|report| report.time < start);
+    reports[first..]
+        .iter()
+        .take_while(|report| report.time <= end)
+        .any(|report| geo::contains(&warning.polygon, (report.lat, report.lon)))
+}
@@ -0,0 +1,356 @@
+use crate::config;
+use crate::product::{Motion, Product, Vtec};
+use chrono::{DateTime, Datelike, Duration, Timelike, Utc};
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+/// Tests if a warning text says the tornado was observed or reported, rather than radar indicated.
+fn is_observed(text: &str) -> bool {
+    text.contains("OBSERVED") || text.contains("reported")
+}
+
+/// How dangerous a warning is, from its text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Emergency,
+    Pds,
+    Observed,
+    Default,
+}
+
+impl Severity {
+    /// Detects the severity of a warning text.
+    ///
+    /// Besides the headline phrases, impact-based warnings tag emergencies with a catastrophic
+    /// damage threat and PDS warnings with a considerable one.
+    pub fn detect(text: &str) -> Self {
+        if text.contains("EMERGENCY") || text.contains("DAMAGE THREAT...CATASTROPHIC") {
+            Severity::Emergency
+        } else if text.contains("PARTICULARLY DANGEROUS SITUATION")
+            || text.contains("DAMAGE THREAT...CONSIDERABLE")
+        {
+            Severity::Pds
+        } else if is_observed(text) {
+            Severity::Observed
+        } else {
+            Severity::Default
+        }
+    }
+
+    /// Every severity, from most to least dangerous.
+    pub const ALL: [Self; 4] = [
+        Severity::Emergency,
+        Severity::Pds,
+        Severity::Observed,
+        Severity::Default,
+    ];
+
+    /// Describes the severity in plain words, for legends.
+    pub fn name(self) -> &'static str {
+        match self {
+            Severity::Emergency => "emergency",
+            Severity::Pds => "PDS",
+            Severity::Observed => "observed",
+            Severity::Default => "radar indicated",
+        }
+    }
+
+    /// Returns the line style for this severity.
+    pub fn style(self, colors: &config::Colors) -> &config::Style {
+        match self {
+            Severity::Emergency => &colors.emergency,
+            Severity::Pds => &colors.pds,
+            Severity::Observed => &colors.observed,
+            Severity::Default => &colors.default,
+        }
+    }
+}
+
+/// Why a product segment was left out of a response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Skip {
+    NoPolygon,
+    BadTime,
+    /// The segment had bytes that weren't utf-8, which most likely garbled it.
+    InvalidUtf8,
+    /// The polygon was off the globe, too small or crossed itself.
+    InvalidPolygon,
+    /// Parsing the segment panicked.
+    Panic,
+}
+
+impl Skip {
+    pub const ALL: [Skip; 5] = [
+        Skip::NoPolygon,
+        Skip::BadTime,
+        Skip::InvalidUtf8,
+        Skip::InvalidPolygon,
+        Skip::Panic,
+    ];
+
+    /// The reason's name in metrics and skip summaries.
+    pub fn name(self) -> &'static str {
+        match self {
+            Skip::NoPolygon => "no_polygon",
+            Skip::BadTime => "bad_time",
+            Skip::InvalidUtf8 => "invalid_utf8",
+            Skip::InvalidPolygon => "invalid_polygon",
+            Skip::Panic => "panic",
+        }
+    }
+}
+
+impl std::fmt::Display for Skip {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Skip::NoPolygon => write!(f, "no polygon found"),
+            Skip::BadTime => write!(f, "no issue time found"),
+            Skip::InvalidUtf8 => write!(f, "invalid utf-8"),
+            Skip::InvalidPolygon => write!(f, "invalid polygon"),
+            Skip::Panic => write!(f, "parser panicked"),
+        }
+    }
+}
+
+/// How many segments were skipped for each reason.
+pub type Skips = std::collections::BTreeMap<Skip, usize>;
+
+/// A single parsed tornado warning.
+#[derive(Debug, Clone, Serialize)]
+pub struct Warning {
+    pub issued: DateTime<Utc>,
+    pub severity: Severity,
+    /// Whether the tornado was observed or reported. Emergencies always count as observed.
+    pub observed: bool,
+    /// The closed polygon as (lat, lon) pairs.
+    pub polygon: Vec<(f32, f32)>,
+    /// The areas listed in the warning, such as `Central Canadian County in central Oklahoma`.
+    pub counties: Vec<String>,
+    /// The maximum hail size tag, in inches.
+    pub max_hail_size: Option<f32>,
+    /// The maximum wind gust tag, in mph.
+    pub max_wind_gust: Option<u32>,
+    /// Whether the warning is tagged with or mentions a waterspout.
+    pub waterspout: bool,
+    /// Where the report came from, such as `Radar indicated rotation`.
+    pub source: Option<String>,
+    pub vtec: Option<Vtec>,
+    /// The UGC codes of the warned counties, such as `OKC017`.
+    pub ugc: Vec<String>,
+    pub motion: Option<Motion>,
+    /// Whether a reported tornado fell inside the polygon while the warning was valid, if reports
+    /// are available.
+    pub verified: Option<bool>,
+    pub text: String,
+}
+
+/// Tests if a warning is valid.
+pub fn is_valid(text: &str) -> bool {
+    !(text.contains("TEST") || text.len() < 50 || text.contains("404"))
+}
+
+/// Parses a tag value with a unit, such as `>2.50 IN`.
+fn measurement<T: std::str::FromStr>(value: &str, unit: &str) -> Option<T> {
+    value
+        .trim_start_matches(['<', '>'])
+        .strip_suffix(unit)?
+        .trim()
+        .parse()
+        .ok()
+}
+
+/// Finds when a product was issued.
+///
+/// Once an event is in progress its VTEC has no begin time, so this falls back to the WMO
+/// heading's day and time, in the month of the event's end.
+fn issued(product: &Product) -> Option<DateTime<Utc>> {
+    let vtec = product.vtec.first()?;
+    if let Some(begin) = vtec.begin {
+        return Some(begin);
+    }
+
+    let end = vtec.end?;
+    let time = &product.wmo.as_ref()?.time;
+    let (day, hour, minute) = (
+        time[..2].parse().ok()?,
+        time[2..4].parse().ok()?,
+        time[4..].parse().ok()?,
+    );
+    let month = if day > end.day() {
+        end.with_day(1)? - Duration::days(1)
+    } else {
+        end
+    };
+
+    month.with_day(day)?.with_hour(hour)?.with_minute(minute)
+}
+
+impl Warning {
+    /// How long the product is valid for, from when it was issued until its VTEC expiration.
+    pub fn duration(&self) -> Option<Duration> {
+        Some(self.vtec.as_ref()?.end? - self.issued)
+    }
+
+    /// Parses a warning from its product text.
+    pub fn parse(text: &str) -> Result<Self, Skip> {
+        lazy_static! {
+            static ref COUNTIES: Regex =
+                Regex::new(r"(?is)tornado warning for\.\.\.\s*\n(.*?)\n\s*\n").unwrap();
+            // How the basis describes a waterspout, since calls to action on land mention them too.
+            static ref WATERSPOUT: Regex =
+                Regex::new(r"(?i)waterspout\s+capable\s+of|tornado\s*/\s*waterspout").unwrap();
+        }
+
+        let product = Product::parse(text);
+
+        let mut polygon = product.polygon.clone();
+        let first = *polygon.first().ok_or(Skip::NoPolygon)?;
+        polygon.push(first);
+
+        let issued = issued(&product).ok_or(Skip::BadTime)?;
+
+        let counties = COUNTIES
+            .captures(text)
+            .map(|cap| {
+                cap[1]
+                    .lines()
+                    .map(|line| line.trim().trim_end_matches('.').to_string())
+                    .filter(|line| !line.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let max_hail_size = product
+            .tag(&["MAX HAIL SIZE", "HAIL"])
+            .and_then(|value| measurement(value, "IN"));
+        let max_wind_gust = product
+            .tag(&["MAX WIND GUST", "WIND"])
+            .and_then(|value| measurement(value, "MPH"));
+
+        let severity = Severity::detect(text);
+
+        let source = product
+            .tag(&["SOURCE"])
+            .map(|source| source.trim_end_matches('.').to_string());
+
+        Ok(Self {
+            issued,
+            severity,
+            observed: severity == Severity::Emergency || is_observed(text),
+            polygon,
+            counties,
+            max_hail_size,
+            max_wind_gust,
+            waterspout: product.tag(&["WATERSPOUT"]).is_some() || WATERSPOUT.is_match(text),
+            source,
+            vtec: product.vtec.into_iter().next(),
+            ugc: product.ugc,
+            motion: product.motion,
+            verified: None,
+            text: text.trim().to_string(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    /// The product segments of the fixture day, with their line endings and control characters
+    /// removed.
+    fn segments() -> Vec<String> {
+        let text = String::from_utf8_lossy(include_bytes!("../tests/fixtures/TOR_20240520.txt"));
+        text.replace(['\r', '\u{1}', '\u{3}'], "")
+            .split("$$")
+            .filter(|segment| is_valid(segment))
+            .map(str::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn new_warnings() {
+        let warning = Warning::parse(&segments()[0]).unwrap();
+
+        assert_eq!(Some(warning.issued), Utc.with_ymd_and_hms(2024, 5, 20, 21, 1, 0).single());
+        assert_eq!(warning.severity, Severity::Pds);
+        assert!(warning.observed);
+        assert_eq!(warning.max_hail_size, Some(1.75));
+        assert_eq!(warning.source.as_deref(), Some("Emergency management confirmed tornado"));
+        assert_eq!(
+            warning.counties,
+            [
+                "Southeastern Canadian County in central Oklahoma",
+                "Northwestern Cleveland County in central Oklahoma",
+            ]
+        );
+        assert_eq!(warning.ugc.len(), 6);
+        // The wrapped vertex is kept, and the ring is closed.
+        assert_eq!(warning.polygon.len(), 6);
+        assert_eq!(warning.polygon.first(), warning.polygon.last());
+        assert_eq!(warning.vtec.unwrap().etn, 20);
+        assert_eq!(warning.motion.unwrap().direction, 270);
+    }
+
+    #[test]
+    fn follow_up_statements() {
+        let warning = Warning::parse(&segments()[1]).unwrap();
+
+        // The VTEC has no begin time, so the WMO heading gives it.
+        assert_eq!(Some(warning.issued), Utc.with_ymd_and_hms(2024, 5, 20, 21, 20, 0).single());
+        assert_eq!(warning.severity, Severity::Emergency);
+        assert_eq!(warning.max_hail_size, Some(2.75));
+        assert_eq!(warning.ugc, ["OKC027"]);
+        assert_eq!(warning.duration(), Some(Duration::minutes(25)));
+    }
+
+    #[test]
+    fn radar_indicated_warnings() {
+        let warning = Warning::parse(&segments()[2]).unwrap();
+
+        assert_eq!(warning.severity, Severity::Default);
+        assert!(!warning.observed);
+        assert!(warning.waterspout);
+        assert_eq!(warning.max_wind_gust, Some(60));
+        assert_eq!(warning.max_hail_size, None);
+    }
+
+    #[test]
+    fn waterspouts() {
+        let land = &segments()[0];
+        let warning = |extra: &str| Warning::parse(&format!("{land}\n{extra}\n")).unwrap();
+
+        assert!(!warning("").waterspout);
+        assert!(!warning("Waterspouts are possible over Lake Thunderbird.").waterspout);
+        assert!(warning("a thunderstorm capable of producing a tornado/waterspout").waterspout);
+        assert!(warning("A waterspout capable of\nmoving onshore was located").waterspout);
+    }
+
+    #[test]
+    fn test_products_are_not_valid() {
+        assert!(segments().iter().all(|segment| !segment.contains("TEST PURPOSES")));
+    }
+
+    #[test]
+    fn garbled_products_are_skipped() {
+        let skips: Vec<_> = segments()[3..]
+            .iter()
+            .map(|segment| Warning::parse(segment).err())
+            .collect();
+
+        // The crossing polygon parses, and is only rejected once validated.
+        let expected = [Some(Skip::NoPolygon), Some(Skip::NoPolygon), None, Some(Skip::BadTime)];
+        assert_eq!(skips, expected);
+    }
+
+    #[test]
+    fn severities() {
+        assert_eq!(Severity::detect("TORNADO EMERGENCY"), Severity::Emergency);
+        assert_eq!(Severity::detect("TORNADO DAMAGE THREAT...CATASTROPHIC"), Severity::Emergency);
+        assert_eq!(Severity::detect("TORNADO DAMAGE THREAT...CONSIDERABLE"), Severity::Pds);
+        assert_eq!(Severity::detect("TORNADO...OBSERVED"), Severity::Observed);
+        assert_eq!(Severity::detect("TORNADO...RADAR INDICATED"), Severity::Default);
+    }
+}
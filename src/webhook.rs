@@ -0,0 +1,183 @@
+use crate::config::{self, Webhook, WebhookKind};
+use crate::event::EventKey;
+use crate::live::{self, Update};
+use crate::warning::Severity;
+use crate::{fetch, HttpError, HttpResult};
+use chrono::{DateTime, Utc};
+use hyper::{Body, Method, Request};
+use serde_json::json;
+use std::collections::HashMap;
+
+/// Discord rejects messages longer than this.
+const DISCORD_LIMIT: usize = 2000;
+
+/// Describes a warning update in a few lines of text.
+fn summary(update: &Update) -> String {
+    let warning = &update.warning;
+    let title = match warning.severity {
+        Severity::Emergency => "Tornado Emergency",
+        Severity::Pds => "PDS Tornado Warning",
+        Severity::Observed => "Observed Tornado Warning",
+        Severity::Default => "Tornado Warning",
+    };
+
+    let polygon: Vec<_> = warning
+        .polygon
+        .iter()
+        .map(|(lat, lon)| format!("{lat}, {lon}"))
+        .collect();
+
+    format!(
+        "**{title}** ({}) issued {}\nAreas: {}\nPolygon: {}",
+        update.event,
+        warning.issued.format("%F %H:%MZ"),
+        warning.counties.join("; "),
+        polygon.join("; ")
+    )
+}
+
+/// Cuts text to at most `limit` characters, ending it with an ellipsis if anything was cut.
+fn truncate(text: &str, limit: usize) -> String {
+    match text.chars().count() <= limit {
+        true => text.to_string(),
+        false => {
+            let mut cut: String = text.chars().take(limit.saturating_sub(1)).collect();
+            cut.push('…');
+            cut
+        }
+    }
+}
+
+/// Builds the body of a webhook request in the format it expects.
+fn payload(kind: WebhookKind, update: &Update) -> String {
+    let summary = summary(update);
+    let text = &update.warning.text;
+
+    match kind {
+        WebhookKind::Discord => {
+            // Discord drops the whole message if it is too long, so the summary is cut to fit, and
+            // the text gets whatever room is left after it and the code block around it.
+            let summary = truncate(&summary, DISCORD_LIMIT);
+            let room = DISCORD_LIMIT.saturating_sub(summary.chars().count() + "\n```\n\n```".len());
+            let content = match room {
+                0 => summary,
+                room => format!("{summary}\n```\n{}\n```", truncate(text, room)),
+            };
+            json!({ "content": content }).to_string()
+        }
+        WebhookKind::Slack => json!({ "text": format!("{summary}\n```{text}```") }).to_string(),
+        // Reuse the serialized warning, since going through a json value widens the coordinates.
+        WebhookKind::Json => format!(r#"{{"event":"{}","warning":{}}}"#, update.event, update.json),
+    }
+}
+
+/// Posts a warning update to a webhook.
+async fn notify(client: &fetch::HttpsClient, webhook: &Webhook, update: &Update) -> HttpResult<()> {
+    let request = Request::builder()
+        .method(Method::POST)
+        .uri(&webhook.url)
+        .header("Content-Type", "application/json")
+        .body(Body::from(payload(webhook.kind, update)))
+        .map_err(HttpError::BuildError)?;
+    let res = client.request(request).await.map_err(HttpError::GetError)?;
+
+    if !res.status().is_success() {
+        return Err(HttpError::StatusError(res.status()));
+    }
+
+    Ok(())
+}
+
+/// Tests if an update should be announced: when its event is issued, or when a later product
+/// raises its severity, such as a warning upgraded to an emergency.
+///
+/// Events first seen in a follow-up were issued before live mode started, and are announced too,
+/// since the follow-up may be the one upgrading them. `severities` holds the latest severity of
+/// each event seen, and is kept up to date.
+fn is_news(update: &Update, severities: &mut HashMap<EventKey, (Severity, DateTime<Utc>)>) -> bool {
+    let warning = &update.warning;
+    // Events that stopped getting updates a day ago have long expired.
+    severities.retain(|_, (_, seen)| Utc::now() - *seen < chrono::Duration::days(1));
+
+    let Some(key) = EventKey::among(warning, |key| severities.contains_key(key)) else {
+        return update.event != "cancelled";
+    };
+    if update.event == "cancelled" {
+        severities.remove(&key);
+        return false;
+    }
+
+    let rank = |severity| Severity::ALL.iter().position(|&s| s == severity);
+    let previous = severities.insert(key, (warning.severity, Utc::now()));
+    match previous {
+        Some((previous, _)) => rank(warning.severity) < rank(previous),
+        None => true,
+    }
+}
+
+/// Notifies the configured webhooks whenever live mode sees a warning with a matching severity
+/// issued, or an event upgraded to one.
+pub fn start() {
+    std::thread::spawn(|| {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let client = fetch::client(&config::get());
+        let mut severities = HashMap::new();
+
+        for update in live::subscribe() {
+            if !is_news(&update, &mut severities) {
+                continue;
+            }
+
+            let config = config::get();
+            let webhooks = config
+                .webhooks
+                .iter()
+                .filter(|webhook| webhook.severities.contains(&update.warning.severity));
+
+            for webhook in webhooks {
+                match runtime.block_on(notify(&client, webhook, &update)) {
+                    Ok(()) => println!("[webhook {}] Notified", webhook.url),
+                    Err(e) => eprintln!("[webhook {}] Failed: {e}", webhook.url),
+                }
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::warning::Warning;
+
+    /// An update for a product of the fixture day.
+    fn update(event: &'static str, product: usize) -> Update {
+        let text = String::from_utf8_lossy(include_bytes!("../tests/fixtures/TOR_20240520.txt"));
+        let text = text.replace(['\r', '\u{1}', '\u{3}'], "");
+        let warning = Warning::parse(text.split("$$").nth(product).unwrap()).unwrap();
+
+        Update {
+            event,
+            warning,
+            json: String::new(),
+        }
+    }
+
+    #[test]
+    fn new_and_upgraded_events() {
+        let mut severities = HashMap::new();
+
+        assert!(is_news(&update("new", 0), &mut severities));
+        // The follow-up upgrades the warning to an emergency.
+        assert!(is_news(&update("updated", 1), &mut severities));
+        assert!(!is_news(&update("updated", 1), &mut severities));
+    }
+
+    #[test]
+    fn events_in_progress_at_startup() {
+        let mut severities = HashMap::new();
+
+        assert!(is_news(&update("updated", 1), &mut severities));
+        assert!(!is_news(&update("updated", 1), &mut severities));
+        assert!(!is_news(&update("cancelled", 1), &mut HashMap::new()));
+    }
+}